@@ -1,25 +1,31 @@
 #[macro_use]
 extern crate tshell;
+#[macro_use]
+extern crate serde_derive;
 
-use tshell::{CommandTree, CommandResult};
+use tshell::{CommandTree, CommandResult, Session};
 use std::collections::HashMap;
+use std::time::Duration;
 
 #[derive(Debug, Default)]
 pub struct Context {
     memory: Option<String>
 }
 
-fn world(args: HashMap<String, &str>, o_context: &mut Option<Context>, history: &HashMap<String, String>) -> CommandResult<Option<String>> {
-    if let Some(context) = history.get("context") {
+fn world(_args: HashMap<String, &str>, o_context: &mut Option<Context>, session: &mut Session) -> CommandResult<Option<String>> {
+    if let Some(context) = session.get("context") {
         println!("Context: {}", context);
     }
-    println!("World");
+    if let Some(ref memory) = o_context.as_ref().and_then(|c| c.memory.as_ref()) {
+        println!("Memory: {}", memory);
+    }
+    shell_println!("World");
     Ok(None)
 }
 
-fn darkness(args: HashMap<String, &str>, o_context: &mut Option<Context>, history: &HashMap<String, String>) -> CommandResult<Option<String>> {
+fn darkness(args: HashMap<String, &str>, _o_context: &mut Option<Context>, _session: &mut Session) -> CommandResult<Option<String>> {
     if let Some(friend) = args.get("friend") {
-        println!("Darkness, friend = {}", friend);
+        shell_println!("Darkness, friend = {}", friend);
     }
     else {
         println!("friend argument is required!");
@@ -27,7 +33,33 @@ fn darkness(args: HashMap<String, &str>, o_context: &mut Option<Context>, histor
     Ok(None)
 }
 
-fn new_context(args: HashMap<String, &str>, o_context: &mut Option<Context>, history: &HashMap<String, String>) -> CommandResult<Option<String>> {
+fn count(_args: HashMap<String, &str>, _o_context: &mut Option<Context>, session: &mut Session) -> CommandResult<Option<String>> {
+    let token = session.cancel_token();
+    let mut seconds = 0;
+    while token.sleep(Duration::from_secs(1)) {
+        seconds += 1;
+        shell_println!("{}", seconds);
+    }
+    shell_println!("Stopped after {} seconds", seconds);
+    Ok(None)
+}
+
+#[derive(Serialize)]
+struct Friend {
+    name: &'static str,
+    since: u32,
+    old: bool
+}
+
+fn friends(_args: HashMap<String, &str>, _o_context: &mut Option<Context>, session: &mut Session) -> CommandResult<Option<String>> {
+    session.output(&[
+        Friend { name: "darkness", since: 1964, old: true },
+        Friend { name: "silence", since: 1965, old: false }
+    ])?;
+    Ok(None)
+}
+
+fn new_context(args: HashMap<String, &str>, _o_context: &mut Option<Context>, _session: &mut Session) -> CommandResult<Option<String>> {
     if let Some(context) = args.get("context") {
         Ok(Some(format!("context:{}", context)))
     }
@@ -39,12 +71,11 @@ fn new_context(args: HashMap<String, &str>, o_context: &mut Option<Context>, his
 
 
 fn main() {
-    let mut context = Context::default();
-    let mut o_context = Some(context);
+    let context = Context::default();
     let mut root = shell_command_tree!{my_cli,
         "MyCLI",
         "0.1.0",
-        o_context.unwrap(),
+        context,
         [
             shell_command_node!{
                 cmd: hello,
@@ -58,8 +89,23 @@ fn main() {
                     shell_command_node!{
                         cmd: darkness,
                         txt_help: "Darkness",
+                        long_help: "Says hello to darkness, your old friend.",
                         callback: darkness,
-                        args: [friend => true]
+                        args: [friend => true],
+                        arg_help: [friend => "Who darkness is"],
+                        choices: [friend => ["old_friend", "stranger"]],
+                        examples: ["hello darkness old_friend"]
+                    },
+                    shell_command_node!{
+                        cmd: friends,
+                        txt_help: "List friends, try output=json",
+                        callback: friends
+                    },
+                    shell_command_node!{
+                        cmd: count,
+                        txt_help: "Count seconds until Ctrl-C",
+                        callback: count,
+                        global: true
                     }
                 ]
             },
@@ -67,6 +113,8 @@ fn main() {
                 cmd: context,
                 txt_help: "Hello Root",
                 callback: new_context,
+                context: true,
+                fallback: true,
                 args: [context => true],
                 nodes: [
                     shell_command_node!{
@@ -83,5 +131,13 @@ fn main() {
                 ]
             }]
         };
-        root.run();
+        root.after_command(|path, _args, _context, result| {
+            if result.is_err() {
+                println!("'{}' failed", path.join(" "));
+            }
+        });
+        root.on_exit(|_context| println!("Bye"));
+        root.set_docs_builtin(true);
+        let outcome = root.run_from_args(std::env::args());
+        std::process::exit(outcome.code);
 }
@@ -1,20 +1,75 @@
 use std::collections::HashMap;
 use std::env;
+use std::fs;
 use std::fmt::Debug;
+use std::io::{self, Write};
 use rustyline::Editor;
 use rustyline::error::ReadlineError;
-use completion::TxCompleter;
+use libc;
+use serde::Serialize;
+use completion::{self, Completion, Shell, TxCompleter, Word};
+use variables::{self, Variables};
+use line::{self, Line, Token};
+use output;
+use format::Format;
+use hooks::Hooks;
+use pager::Pager;
+use signal::SigintGuard;
+use session::{Frame, Session};
+use prompt::PromptRenderer;
+use help::{DetailHelp, HelpEntry, HelpRenderer, ManHelp, MarkdownHelp, TextHelp};
+use theme::{self, Style, Theme};
 use std::sync::{Arc, Mutex};
 
 pub type CommandResult<R> = Result<R, String>;
-pub type CallBack<T> = Fn(HashMap<String, &str>, &mut Option<T>, &HashMap<String, String>) -> CommandResult<Option<String>>;
+
+/// Status of a line whose command doesn't exist, as in POSIX shells.
+pub const NOT_FOUND: i32 = 127;
+pub type CallBack<T> = dyn Fn(HashMap<String, &str>, &mut Option<T>, &mut Session) -> CommandResult<Option<String>>;
+
+/// What `CommandTree::run` hands back to its caller once the shell exits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RunOutcome {
+    pub code: i32
+}
+
+/// Argument of a command. Required ones are positional, optional ones are
+/// given as `name=value` and fall back to `default` when left out.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Arg {
+    pub name: String,
+    pub required: bool,
+    pub default: Option<String>,
+    pub help: Option<String>,
+    /// Values offered by completion, the arg still takes any value.
+    pub choices: Vec<String>
+}
+
+impl Arg {
+    pub fn new(name: &str, required: bool) -> Arg {
+        Arg {
+            name: name.to_owned(),
+            required,
+            default: None,
+            help: None,
+            choices: Vec::new()
+        }
+    }
+}
 
 pub struct Node<T> {
     pub cmd: String,
     hidden: bool,
     help: String,
-    args: Option<Vec<(String, bool)>>,
+    long_help: String,
+    examples: Vec<String>,
+    args: Option<Vec<Arg>>,
     sub_nodes: Option<Vec<Node<T>>>,
+    confirm: Option<String>,
+    global: bool,
+    fallback: bool,
+    context: bool,
+    format: Option<Format>,
     pub callback: Option<Box<CallBack<T>>>
 }
 
@@ -22,24 +77,111 @@ impl <T>Node<T> {
     pub fn new(cmd: &str,  conditional: Option<&str>, help: &str, callback: Option<Box<CallBack<T>>>) -> Node<T> {
 
         let hidden = if let Some(c) = conditional {
-            match env::var(&c){
-                Ok(_) => false,
-                Err(_) => true
-            }
+            env::var(c).is_err()
         } else {
             false
         };
 
         Node {
             cmd: cmd.to_owned(),
-            hidden: hidden,
+            hidden,
             help: help.to_owned(),
+            long_help: String::new(),
+            examples: Vec::new(),
             args: None,
             sub_nodes: None,
-            callback: callback
+            confirm: None,
+            global: false,
+            fallback: false,
+            context: false,
+            format: None,
+            callback
         }
     }
 
+    pub fn set_help(&mut self, help: &str) {
+        self.help = help.to_owned();
+    }
+
+    /// Description shown below the one line help by `help <cmd>` and `<cmd> ?`.
+    pub fn set_long_help(&mut self, long_help: &str) {
+        self.long_help = long_help.to_owned();
+    }
+
+    /// Adds a usage example, e.g. `"interface eth0"`.
+    pub fn add_example(&mut self, example: &str) {
+        self.examples.push(example.to_owned());
+    }
+
+    pub fn set_conditional(&mut self, conditional: &str) {
+        self.hidden = env::var(conditional).is_err();
+    }
+
+    pub fn set_callback(&mut self, callback: Box<CallBack<T>>) {
+        self.callback = Some(callback);
+    }
+
+    /// A callback returning a value instead of printing, written with
+    /// `Session::output` in the line's format.
+    pub fn set_structured_callback<S, F>(&mut self, callback: F)
+        where S: Serialize,
+              F: Fn(HashMap<String, &str>, &mut Option<T>, &mut Session) -> CommandResult<S> + 'static
+    {
+        self.callback = Some(Box::new(move |args, context, session| {
+            let value = callback(args, context, session)?;
+            session.output(&value)?;
+            Ok(None)
+        }));
+    }
+
+    /// Format of this command's output when neither the line nor the `output`
+    /// variable choose one, e.g. `Format::Table` for a listing.
+    pub fn set_output_format(&mut self, format: Format) {
+        self.format = Some(format);
+    }
+
+    pub fn output_format(&self) -> Option<Format> {
+        self.format
+    }
+
+    /// Ask the user `msg` before the callback is invoked, e.g. "Reset all counters?"
+    pub fn set_confirm(&mut self, msg: &str) {
+        self.confirm = Some(msg.to_owned());
+    }
+
+    pub fn confirm(&self) -> &Option<String> {
+        &self.confirm
+    }
+
+    /// Global commands can be run from any context, e.g. `show` or `ping`.
+    pub fn set_global(&mut self, global: bool) {
+        self.global = global;
+    }
+
+    pub fn is_global(&self) -> bool {
+        self.global
+    }
+
+    /// Inside this node's context, commands not found below it are looked up
+    /// in its ancestors, nearest first.
+    pub fn set_fallback(&mut self, fallback: bool) {
+        self.fallback = fallback;
+    }
+
+    pub fn fallback(&self) -> bool {
+        self.fallback
+    }
+
+    /// Marks the command as entering a context, its callback returns
+    /// `"object:label"` and the sub commands run inside it.
+    pub fn set_context(&mut self, context: bool) {
+        self.context = context;
+    }
+
+    pub fn is_context(&self) -> bool {
+        self.context
+    }
+
     pub fn add_node(&mut self, node: Node<T>) {
         if let Some(ref mut nodes) = self.sub_nodes {
             nodes.push(node)
@@ -51,20 +193,86 @@ impl <T>Node<T> {
 
     pub fn add_arg(&mut self, arg: &str, required: bool) {
         if let Some(ref mut args) = self.args {
-            args.push((arg.to_owned(), required));
+            args.push(Arg::new(arg, required));
         }
         else {
-            let array = vec![(arg.to_owned(), required)];
+            let array = vec![Arg::new(arg, required)];
             self.args= Some(array);
         }
     }
 
+    /// Value an optional arg gets when it isn't given on the line.
+    pub fn set_arg_default(&mut self, arg: &str, default: &str) {
+        if let Some(arg) = self.args.iter_mut().flatten().find(|a| a.name == arg) {
+            arg.default = Some(default.to_owned());
+        }
+    }
+
+    pub fn set_arg_help(&mut self, arg: &str, help: &str) {
+        if let Some(arg) = self.args.iter_mut().flatten().find(|a| a.name == arg) {
+            arg.help = Some(help.to_owned());
+        }
+    }
+
+    /// Values completion offers for `arg`, e.g. `["up", "down"]`.
+    pub fn set_arg_choices(&mut self, arg: &str, choices: &[&str]) {
+        if let Some(arg) = self.args.iter_mut().flatten().find(|a| a.name == arg) {
+            arg.choices = choices.iter().map(|choice| (*choice).to_owned()).collect();
+        }
+    }
+
+    /// Completions for `partial` as an optional arg: `name=` for the args it
+    /// starts, `name=choice` once the `=` is typed.
+    fn optional_suggestions(&self, partial: &str) -> Vec<String> {
+        let optional = self.args.iter().flatten().filter(|a| !a.required);
+        match partial.find('=') {
+            Some(pos) => {
+                let (name, value) = (&partial[..pos], &partial[pos + 1..]);
+                optional.filter(|a| a.name == name)
+                    .flat_map(|a| a.choices.iter())
+                    .filter(|choice| choice.starts_with(value))
+                    .map(|choice| format!("{}={}", name, choice))
+                    .collect()
+            },
+            None => {
+                optional.filter(|a| a.name.starts_with(partial))
+                    .map(|a| format!("{}=", a.name))
+                    .collect()
+            }
+        }
+    }
+
     pub fn find(&self, cmd: &str) -> Option<&Node<T>> {
         debug!("Find: {}", cmd);
-        if cmd == "?" || cmd == "" {
-            self.print_help(1);
+        if cmd == "?" || cmd.is_empty() {
             return None;
         }
+        self.child(cmd)
+    }
+
+    /// All visible global nodes below this one, with the command path of their
+    /// parent relative to this node.
+    pub fn globals(&self) -> Vec<(Vec<&str>, &Node<T>)> {
+        let mut results = Vec::new();
+        self.collect_globals(&mut Vec::new(), &mut results);
+        results
+    }
+
+    fn collect_globals<'a>(&'a self, path: &mut Vec<&'a str>, results: &mut Vec<(Vec<&'a str>, &'a Node<T>)>) {
+        if let Some(ref nodes) = self.sub_nodes {
+            for node in nodes.iter().filter(|node| !node.hidden) {
+                if node.global {
+                    results.push((path.clone(), node));
+                }
+                path.push(&node.cmd);
+                node.collect_globals(path, results);
+                path.pop();
+            }
+        }
+    }
+
+    /// Looks up a visible sub command, without `find`'s help side effect.
+    pub fn child(&self, cmd: &str) -> Option<&Node<T>> {
         if let Some(ref nodes) = self.sub_nodes {
             for node in nodes.iter() {
                 if node.cmd == cmd && !node.hidden{
@@ -116,189 +324,144 @@ impl <T>Node<T> {
         }
     }
 
-    pub fn args(&self) -> &Option<Vec<(String, bool)>> {
+    pub fn args(&self) -> &Option<Vec<Arg>> {
         &self.args
     }
 
-    pub fn print_help(&self, level: u8) {
-        if self.hidden {
-            return;
+    /// Help for this node and everything below it.
+    pub fn help_entry(&self) -> HelpEntry {
+        HelpEntry {
+            name: self.cmd.clone(),
+            description: self.help.clone(),
+            long_description: self.long_help.clone(),
+            examples: self.examples.clone(),
+            args: self.args.clone().unwrap_or_default(),
+            subcommands: self.sub_nodes.iter().flatten().map(|node| node.help_entry()).collect(),
+            hidden: self.hidden,
+            context: self.context
         }
-        if level > 0 {
-            for _ in 0..level {
-                print!("  ");
-            }
-            print!("{}:\t{}", self.cmd, self.help);
-            if let Some(ref args) = self.args {
-                let mut required = Vec::with_capacity(args.len());
-                let mut optional = Vec::with_capacity(args.len());
+    }
 
-                for &(ref arg, ref req) in args.iter() {
-                    if *req {
-                        required.push(arg);
-                    }
-                    else {
-                        optional.push(arg);
-                    }
-                }
+    pub fn print_help(&self, level: u8, theme: Option<&Theme>) {
+        shell_print!("{}", TextHelp::new(level as usize).theme(theme).render(&self.help_entry()));
+    }
 
-                if required.len() > 0 || optional.len() > 0 {
-                    print!(", Format: {} ", self.cmd);
-                    for arg in required {
-                        print!("<{}> ", arg);
-                    }
-                    for arg in optional {
-                        print!("[{}] ", arg);
-                    }
-                    if self.sub_nodes.is_some() {
-                        print!("[enter]");
-                    }
-                }
-            }
-            println!("");
-        }
-        else {
-            println!("{}: {}", self.cmd, self.help);
-            println!("-------------------");
-            println!("Commands:");
-        }
-        if let Some(ref nodes) = self.sub_nodes {
-            for node in nodes {
-                node.print_help(level + 1)
-            };
-        }
+    /// Usage, description, args, examples and sub commands of this node.
+    pub fn print_long_help(&self, theme: Option<&Theme>) {
+        shell_print!("{}", DetailHelp::new().theme(theme).render(&self.help_entry()));
     }
 }
 
 #[macro_export]
 macro_rules! shell_command_node {
     (
-        cmd: $name:ident,
-        txt_help: $help:expr
-    ) => {
-        $crate::commands::Node::new(stringify!($name), None, $help, None);
-    };
-    (
-        cmd: $name:ident,
-        txt_help: $help:expr,
-        nodes: [ $( $node:expr ),* ]
+        cmd: $name:ident
+        $(, $($rest:tt)*)?
     ) => {
         {
-            let mut this_node = $crate::commands::Node::new(stringify!($name), None, $help, None);
-            $(
-                this_node.add_node($node);
-            )*
+            let mut this_node = $crate::commands::Node::new(stringify!($name), None, "", None);
+            shell_command_node!(@field this_node; $($($rest)*)?);
             this_node
         }
     };
-    (
-        cmd: $name:ident,
-        txt_help: $help:expr,
-        args: [ $( $arg:ident => $required:expr ),* ]
-    ) => {
-        {
-            let mut this_node = $crate::commands::Node::new(stringify!($name), None, $help, None);
-            $(
-                this_node.add_arg(stringify!($arg), $required);
-            )*
-            this_node
-        }
+    (@field $this:ident; ) => {};
+    (@field $this:ident; , $($rest:tt)*) => {
+        shell_command_node!(@field $this; $($rest)*);
     };
-    (
-        cmd: $name:ident,
-        conditional: $cond:expr,
-        txt_help: $help:expr,
-        nodes: [ $( $node:expr ),* ]
-    ) => {
-        {
-            let mut this_node = $crate::commands::Node::new(stringify!($name), Some($cond), $help, None);
-            $(
-                this_node.add_node($node);
-            )*
-            this_node
-        }
+    (@field $this:ident; conditional: $cond:expr $(, $($rest:tt)*)?) => {
+        $this.set_conditional($cond);
+        shell_command_node!(@field $this; $($($rest)*)?);
     };
-    (
-        cmd: $name:ident,
-        txt_help: $help:expr,
-        callback: $callback:expr,
-        args: [ $( $arg:ident => $required:expr ),* ],
-        nodes: [ $( $node:expr ),* ]
-    ) => {
-        {
-            let mut this_node = $crate::commands::Node::new(stringify!($name), None, $help, Some(Box::new($callback)));
-            $(
-                this_node.add_node($node);
-            )*
-            $(
-                this_node.add_arg(stringify!($arg), $required);
-            )*
-            this_node
-        }
+    (@field $this:ident; txt_help: $help:expr $(, $($rest:tt)*)?) => {
+        $this.set_help($help);
+        shell_command_node!(@field $this; $($($rest)*)?);
     };
-    (
-        cmd: $name:ident,
-        txt_help: $help:expr,
-        callback: $callback:expr
-    ) => {
-        $crate::commands::Node::new(stringify!($name), None, $help, Some(Box::new($callback)));
+    (@field $this:ident; callback: $callback:expr $(, $($rest:tt)*)?) => {
+        $this.set_callback(Box::new($callback));
+        shell_command_node!(@field $this; $($($rest)*)?);
     };
-    (
-        cmd: $name:ident,
-        txt_help: $help:expr,
-        callback: $callback:expr,
-        nodes: [ $( $node:expr ),* ]
-    ) => {
-        {
-            let mut this_node = $crate::commands::Node::new(stringify!($name), None, $help, Some(Box::new($callback)));
-            $(
-                this_node.add_node($node);
-            )*
-            this_node
-        }
+    (@field $this:ident; structured: $callback:expr $(, $($rest:tt)*)?) => {
+        $this.set_structured_callback($callback);
+        shell_command_node!(@field $this; $($($rest)*)?);
     };
-    (
-        cmd: $name:ident,
-        txt_help: $help:expr,
-        callback: $callback:expr,
-        args: [ $( $arg:ident => $required:expr ),* ]
-    ) => {
-        {
-            let mut this_node = $crate::commands::Node::new(stringify!($name), None, $help, Some(Box::new($callback)));
-            $(
-                this_node.add_arg(stringify!($arg), $required);
-            )*
-            this_node
-        }
+    (@field $this:ident; output: $format:expr $(, $($rest:tt)*)?) => {
+        $this.set_output_format($format);
+        shell_command_node!(@field $this; $($($rest)*)?);
     };
-    (
-        cmd: $name:ident,
-        txt_help: $help:expr,
-        callback: $callback:expr,
-        args: [ $( $arg:ident => $required:expr ),* ],
-        nodes: [ $( $node:expr ),* ]
-    ) => {
-        {
-            let mut this_node = $crate::commands::Node::new(stringify!($name), None, $help, Some(Box::new($callback)));
-            $(
-                this_node.add_node($node);
-            )*
-            $(
-                this_node.add_arg(stringify!($arg), $required);
-            )*
-            this_node
-        }
+    (@field $this:ident; global: $global:expr $(, $($rest:tt)*)?) => {
+        $this.set_global($global);
+        shell_command_node!(@field $this; $($($rest)*)?);
+    };
+    (@field $this:ident; context: $context:expr $(, $($rest:tt)*)?) => {
+        $this.set_context($context);
+        shell_command_node!(@field $this; $($($rest)*)?);
+    };
+    (@field $this:ident; fallback: $fallback:expr $(, $($rest:tt)*)?) => {
+        $this.set_fallback($fallback);
+        shell_command_node!(@field $this; $($($rest)*)?);
+    };
+    (@field $this:ident; long_help: $help:expr $(, $($rest:tt)*)?) => {
+        $this.set_long_help($help);
+        shell_command_node!(@field $this; $($($rest)*)?);
+    };
+    (@field $this:ident; examples: [ $( $example:expr ),* ] $(, $($rest:tt)*)?) => {
+        $(
+            $this.add_example($example);
+        )*
+        shell_command_node!(@field $this; $($($rest)*)?);
+    };
+    (@field $this:ident; confirm: $msg:expr $(, $($rest:tt)*)?) => {
+        $this.set_confirm($msg);
+        shell_command_node!(@field $this; $($($rest)*)?);
+    };
+    (@field $this:ident; args: [ $( $arg:ident => $required:expr ),* ] $(, $($rest:tt)*)?) => {
+        $(
+            $this.add_arg(stringify!($arg), $required);
+        )*
+        shell_command_node!(@field $this; $($($rest)*)?);
+    };
+    (@field $this:ident; defaults: [ $( $arg:ident => $default:expr ),* ] $(, $($rest:tt)*)?) => {
+        $(
+            $this.set_arg_default(stringify!($arg), $default);
+        )*
+        shell_command_node!(@field $this; $($($rest)*)?);
+    };
+    (@field $this:ident; arg_help: [ $( $arg:ident => $help:expr ),* ] $(, $($rest:tt)*)?) => {
+        $(
+            $this.set_arg_help(stringify!($arg), $help);
+        )*
+        shell_command_node!(@field $this; $($($rest)*)?);
+    };
+    (@field $this:ident; choices: [ $( $arg:ident => [ $( $choice:expr ),* ] ),* ] $(, $($rest:tt)*)?) => {
+        $(
+            $this.set_arg_choices(stringify!($arg), &[ $( $choice ),* ]);
+        )*
+        shell_command_node!(@field $this; $($($rest)*)?);
+    };
+    (@field $this:ident; nodes: [ $( $node:expr ),* ] $(, $($rest:tt)*)?) => {
+        $(
+            $this.add_node($node);
+        )*
+        shell_command_node!(@field $this; $($($rest)*)?);
     };
 }
 
-
 pub struct CommandTree<T>
     where T: Debug
 {
     name: String,
     version: String,
     root: Node<T>,
-    context: Arc<Mutex<Option<T>>>
+    pub(crate) context: Arc<Mutex<Option<T>>>,
+    assume_yes: bool,
+    exit_on_eof: bool,
+    docs_builtin: bool,
+    format: Format,
+    theme: Theme,
+    color: Option<bool>,
+    pager: Pager,
+    hooks: Hooks<T>,
+    prompt: Option<Box<dyn PromptRenderer<T>>>
 }
 
 impl <T>CommandTree<T>
@@ -308,47 +471,281 @@ impl <T>CommandTree<T>
         CommandTree {
             name: name.to_owned(),
             version: version.to_owned(),
-            root: root,
-            context: Arc::new(Mutex::new((context)))
+            root,
+            context: Arc::new(Mutex::new(context)),
+            assume_yes: false,
+            exit_on_eof: true,
+            docs_builtin: false,
+            format: Format::Text,
+            theme: Theme::new(),
+            color: None,
+            pager: Pager::More,
+            hooks: Hooks::new(),
+            prompt: None
         }
     }
 
+    /// Whether Ctrl-D at the top level exits the shell, on by default. Inside a
+    /// context it always moves up a level. When input isn't a terminal EOF exits
+    /// regardless, there's nothing more to read.
+    pub fn set_exit_on_eof(&mut self, exit: bool) {
+        self.exit_on_eof = exit;
+    }
+
+    /// Enables the hidden `__docs man|markdown|bash|zsh|fish [file]` builtin, which prints
+    /// or writes the generated reference, e.g. from a build script driving the shell.
+    pub fn set_docs_builtin(&mut self, enabled: bool) {
+        self.docs_builtin = enabled;
+    }
+
+    /// Default format for structured output, see `Session::output`.
+    pub fn set_output_format(&mut self, format: Format) {
+        self.format = format;
+    }
+
+    pub(crate) fn new_session(&self) -> Session {
+        let mut session = Session::new();
+        session.format = self.format;
+        session.theme = self.colors().cloned();
+        session.pager = self.pager.clone();
+        session
+    }
+
+    /// How long output is paged, `Pager::More` by default. Paging only happens
+    /// when input and output are a terminal, `terminal length 0` turns it off.
+    pub fn set_pager(&mut self, pager: Pager) {
+        self.pager = pager;
+    }
+
+    /// Colors for prompts, help and errors. Colors are left out anyway when
+    /// `NO_COLOR` is set, with `--no-color` or when stdout isn't a terminal.
+    pub fn set_theme(&mut self, theme: Theme) {
+        self.theme = theme;
+    }
+
+    /// Turns colors on or off whatever the terminal, `--no-color` turns them off.
+    pub fn set_color(&mut self, enabled: bool) {
+        self.color = Some(enabled);
+    }
+
+    /// The theme, unless colors are off.
+    fn colors(&self) -> Option<&Theme> {
+        if self.color.unwrap_or_else(theme::color_enabled) {
+            Some(&self.theme)
+        }
+        else {
+            None
+        }
+    }
+
+    /// Replaces the default `name/context>>` prompt, with a closure or a
+    /// `PromptFormat` such as `PromptFormat::new("%p [%s]> ")`.
+    pub fn set_prompt<R>(&mut self, renderer: R)
+        where R: PromptRenderer<T> + 'static
+    {
+        self.prompt = Some(Box::new(renderer));
+    }
+
+    /// Runs once `run` starts, before the first prompt.
+    pub fn on_start<F>(&mut self, hook: F)
+        where F: Fn(&mut Option<T>) + 'static
+    {
+        self.hooks.add_on_start(Box::new(hook));
+    }
+
+    /// Runs before every command callback with the command path and args,
+    /// an error keeps the callback from running.
+    pub fn before_command<F>(&mut self, hook: F)
+        where F: Fn(&[&str], &HashMap<String, &str>, &mut Option<T>) -> CommandResult<()> + 'static
+    {
+        self.hooks.add_before_command(Box::new(hook));
+    }
+
+    /// Runs after every command callback with its result.
+    pub fn after_command<F>(&mut self, hook: F)
+        where F: Fn(&[&str], &HashMap<String, &str>, &mut Option<T>, &CommandResult<Option<String>>) + 'static
+    {
+        self.hooks.add_after_command(Box::new(hook));
+    }
+
+    /// Runs when the shell exits, before `run` returns.
+    pub fn on_exit<F>(&mut self, hook: F)
+        where F: Fn(&mut Option<T>) + 'static
+    {
+        self.hooks.add_on_exit(Box::new(hook));
+    }
+
+    /// Same as `on_exit`, kept for trees written before the other hooks.
+    pub fn add_shutdown_hook<F>(&mut self, hook: F)
+        where F: Fn(&mut Option<T>) + 'static
+    {
+        self.on_exit(hook);
+    }
+
+    pub(crate) fn start(&self) {
+        self.with_context(|context| self.hooks.start(context));
+    }
+
+    pub(crate) fn exit(&self) {
+        self.with_context(|context| self.hooks.exit(context));
+    }
+
+    fn with_context<F>(&self, f: F)
+        where F: FnOnce(&mut Option<T>)
+    {
+        match self.context.lock() {
+            Ok(mut context) => f(&mut context),
+            Err(e) => output::error(&format!("could not lock context: {}", e))
+        }
+    }
+
+    /// Skip confirmation prompts, as if every command was run with `--yes`.
+    /// Meant for scripts and batch runs where nobody is there to answer.
+    pub fn set_assume_yes(&mut self, yes: bool) {
+        self.assume_yes = yes;
+    }
+
+    /// Help for the whole tree, to render with any `HelpRenderer`.
+    pub fn help_entry(&self) -> HelpEntry {
+        self.root.help_entry()
+    }
+
     pub fn get_help(&self) {
-        self.root.print_help(0);
+        self.root.print_help(0, self.colors());
+    }
+
+    /// The tree as a roff man page, e.g. to install as `man1/<name>.1`.
+    pub fn man_page(&self) -> String {
+        ManHelp::new(&self.version).render(&self.help_entry())
+    }
+
+    /// Completion script for `shell`, for running the shell's commands straight
+    /// from the command line, e.g. `my_cli hello world`.
+    pub fn completion_script(&self, shell: Shell) -> String {
+        completion::script(shell, &self.name, &self.help_entry())
+    }
+
+    /// The tree as a Markdown command reference.
+    pub fn markdown_reference(&self) -> String {
+        MarkdownHelp.render(&self.help_entry())
+    }
+
+    /// Completes the word under `cursor`, a byte offset in `line`, typed at the
+    /// top level. Nothing is printed, e.g. to test completion.
+    pub fn complete(&self, line: &str, cursor: usize) -> Completion {
+        self.complete_in(&[], line, cursor)
+    }
+
+    /// Same as `complete` for a line typed in the context at `context`, a
+    /// command path from the root.
+    pub fn complete_in(&self, context: &[&str], line: &str, cursor: usize) -> Completion {
+        output::begin_capture();
+        let completion = self.completion(context, line, cursor);
+        output::discard_capture();
+        completion
+    }
+
+    /// Completion as the line editor does it, which also shows help and
+    /// missing args while completing.
+    pub(crate) fn completion(&self, context: &[&str], line: &str, cursor: usize) -> Completion {
+        let word = Word::at(line, cursor);
+        let mut levels: Vec<&str> = word.before.iter().map(|word| word.as_str()).collect();
+        if !levels.is_empty() || !word.prefix.is_empty() {
+            levels.push(&word.prefix);
+        }
+        let candidates = self.suggestions(levels, context)
+            .unwrap_or_default()
+            .into_iter()
+            .map(completion::quote_candidate)
+            .collect();
+        Completion {
+            start: word.start,
+            end: word.end,
+            candidates
+        }
     }
 
-    pub fn get_suggestions<'a>(&'a self, line: &str, o_sub_node: Option<&'a Node<T>>) -> Option<Vec<&str>> {
-        let mut levels: Vec<&str> = line.trim_left().split_whitespace().collect();
+    /// Completion candidates for `line` typed in the context at `context` (a command
+    /// path from the root), understands `/cmd` and `../cmd` like the dispatcher.
+    pub fn get_suggestions(&self, line: &str, context: &[&str]) -> Option<Vec<String>> {
+        let mut levels: Vec<&str> = line.split_whitespace().collect();
         if line.ends_with(char::is_whitespace) {
             levels.push("");
         }
+        self.suggestions(levels, context)
+    }
+
+    fn suggestions(&self, mut levels: Vec<&str>, context: &[&str]) -> Option<Vec<String>> {
         debug!("Levels: {:?}", levels);
-        let mut node = &self.root;
-        if let Some(sub_node) = o_sub_node {
-            node = sub_node;
+        let mut start: Vec<String> = context.iter().map(|cmd| (*cmd).to_owned()).collect();
+        let mut prefix = "";
+        if let Some(first) = levels.first().cloned() {
+            if is_path(first) {
+                if levels.len() == 1 {
+                    if first == ".." {
+                        return Some(vec!["../".to_owned()]);
+                    }
+                    let pos = first.rfind('/').map(|pos| pos + 1).unwrap_or(0);
+                    let (dir, partial) = first.split_at(pos);
+                    let (dir_start, cmds) = resolve_path(&start, dir).ok()?;
+                    start = dir_start;
+                    levels = cmds;
+                    if !partial.is_empty() {
+                        levels.push(partial);
+                    }
+                    prefix = dir;
+                }
+                else {
+                    let (dir_start, cmds) = resolve_path(&start, first).ok()?;
+                    start = dir_start;
+                    levels.splice(0..1, cmds);
+                }
+            }
+        }
+        let context = start.clone();
+        if levels.len() > 1 {
+            let start_node = self.find_node(&to_refs(&start))?;
+            if start_node.child(levels[0]).is_none() {
+                if let Some((parent, _)) = self.find_fallback(&start, levels[0]) {
+                    start = parent;
+                }
+            }
         }
+        let mut node = self.find_node(&to_refs(&start))?;
 
         let mut i = 0;
         let mut nr_required = 0;
+        let mut optional = Vec::new();
         while i != levels.len() {
             debug!("i: {}, looking for: {}", i, levels[i]);
+            if i + 1 == levels.len() {
+                optional = node.optional_suggestions(levels[i]);
+            }
+            else if levels[i].contains('=') {
+                // An optional arg already given, e.g. `count=5` in `ping host count=5 s`
+                i += 1;
+                continue;
+            }
             node = match node.find(levels[i]) {
                 Some(current_node) => {
                     if let Some(ref args) = current_node.args {
-                        let required: Vec<&str> = args.iter().filter_map(|a| {
-                            if a.1 {
-                                Some(a.0.as_str())
-                            }
-                            else {
-                                None
-                            }
-                        }).collect();
+                        let required: Vec<&Arg> = args.iter().filter(|a| a.required).collect();
 
                         nr_required = required.len();
-                        for (x, _) in required.iter().enumerate() {
-                            if let None = levels.get(i + 1) {
-                                println!("'{}' missing fields: {:?}", current_node.cmd, required[x..].to_vec());
-                                return None;
+                        for (x, arg) in required.iter().enumerate() {
+                            match levels.get(i + 1) {
+                                None => {
+                                    let names: Vec<&str> = required[x..].iter().map(|a| a.name.as_str()).collect();
+                                    shell_println!("'{}' missing fields: {:?}", current_node.cmd, names);
+                                    return None;
+                                },
+                                Some(value) if i + 2 == levels.len() => {
+                                    return Some(arg.choices.iter()
+                                        .filter(|choice| choice.starts_with(value))
+                                        .cloned()
+                                        .collect());
+                                },
+                                Some(_) => ()
                             }
                             i += 1;
                         }
@@ -363,331 +760,907 @@ impl <T>CommandTree<T>
             i += 1;
         }
 
-        node.get_suggestions(levels, i, nr_required)
+        let partial = levels.first().cloned().unwrap_or("");
+        let first_word = i == 0 && levels.len() <= 1;
+        let mut suggestions: Vec<String> = node.get_suggestions(levels, i, nr_required)
+            .map(|entries| entries.iter().map(|entry| format!("{}{}", prefix, entry)).collect())
+            .unwrap_or_default();
+        if first_word && prefix.is_empty() {
+            let (globals, inherited) = self.outside_commands(&context);
+            for other in globals.iter().chain(inherited.iter()) {
+                if other.cmd.starts_with(partial) {
+                    suggestions.push(other.cmd.to_owned());
+                }
+            }
+        }
+        suggestions.extend(optional);
+        Some(suggestions)
     }
 
-    fn gen_prompt(&self, o_my_context: &Option<Vec<(&Node<T>, String)>>) -> String {
-        if let Some(ref my_context) = *o_my_context {
-            let mut temp_prompt = format!("{}", self.name);
-            for entry in my_context.iter() {
-                temp_prompt = format!("{}/{}", temp_prompt, entry.1);
+    /// Looks `cmd` up outside of the context at `path`: in its ancestors if the
+    /// context node falls back, then among the global commands. Returns the path
+    /// and node of the command's parent.
+    fn find_fallback(&self, path: &[String], cmd: &str) -> Option<(Vec<String>, &Node<T>)> {
+        let refs = to_refs(path);
+        let context = self.find_node(&refs)?;
+        if context.fallback {
+            for len in (0..refs.len()).rev() {
+                if let Some(parent) = self.find_node(&refs[..len]) {
+                    if parent.child(cmd).is_some() {
+                        return Some((path[..len].to_vec(), parent));
+                    }
+                }
             }
-            format!("\x1b[1;32m{}>>\x1b[0m ", temp_prompt)
         }
-        else {
-            format!("\x1b[1;32m{}>>\x1b[0m ", self.name)
+        let (parent_path, _) = self.root.globals().into_iter().find(|&(_, node)| node.cmd == cmd)?;
+        let parent = self.find_node(&parent_path)?;
+        Some((parent_path.iter().map(|cmd| (*cmd).to_owned()).collect(), parent))
+    }
+
+    /// Whether `cmd` is a command of the current context, one it falls back
+    /// to or a global one.
+    fn has_command(&self, session: &Session, cmd: &str) -> bool {
+        let path: Vec<String> = session.path().iter().map(|cmd| (*cmd).to_owned()).collect();
+        self.find_node(&to_refs(&path)).and_then(|node| node.child(cmd)).is_some()
+            || self.find_fallback(&path, cmd).is_some()
+    }
+
+    /// Looks up the command `words` names from the context at `path`, the same
+    /// way the dispatcher does, e.g. for `help interface vlan`.
+    fn lookup(&self, path: &[String], words: &[&str]) -> Result<&Node<T>, String> {
+        let mut start = path.to_vec();
+        let mut words = words.to_vec();
+        if let Some(first) = words.first().cloned() {
+            if is_path(first) {
+                let (dir_start, cmds) = resolve_path(&start, first)?;
+                start = dir_start;
+                words.splice(0..1, cmds);
+            }
+        }
+        let mut node = self.find_node(&to_refs(&start))
+            .ok_or_else(|| format!("command '{}' not found", start.join(" ")))?;
+        if let Some(first) = words.first() {
+            if node.child(first).is_none() {
+                if let Some((_, parent)) = self.find_fallback(&start, first) {
+                    node = parent;
+                }
+            }
+        }
+        for word in words {
+            node = node.child(word).ok_or_else(|| format!("command '{}' not found", word))?;
+        }
+        Ok(node)
+    }
+
+    /// The `help` builtin: help for the current context, for the command at a
+    /// path, for the whole tree (`--all`) or for commands matching `--search kw`.
+    fn help(&self, words: &[&str], session: &Session) -> CommandResult<()> {
+        let path: Vec<String> = session.path().iter().map(|cmd| (*cmd).to_owned()).collect();
+        match words.first() {
+            None => {
+                match self.current_node(session) {
+                    Some(node) => {
+                        node.print_help(0, session.theme());
+                        self.print_outside_help(&path, session.theme());
+                    },
+                    None => {
+                        shell_print!("{}", TextHelp::new(0).depth(1).theme(session.theme()).render(&self.root.help_entry()));
+                        shell_println!("\nhelp <command> for details, help --all for every command");
+                    }
+                }
+            },
+            Some(&"--all") => self.root.print_help(0, session.theme()),
+            Some(&"--search") => {
+                let keyword = words[1..].join(" ");
+                if keyword.is_empty() {
+                    return Err("help --search needs a keyword".to_owned());
+                }
+                let entry = self.help_entry();
+                let matches = entry.search(&keyword);
+                if matches.is_empty() {
+                    shell_println!("No commands match '{}'", keyword);
+                    return Ok(());
+                }
+                let rows: Vec<(String, &str)> = matches.iter()
+                    .map(|&(ref path, entry)| (format!("/{}", path.join(" ")), entry.description.as_str()))
+                    .collect();
+                let width = rows.iter().map(|row| row.0.chars().count()).max().unwrap_or(0);
+                for (cmd, description) in rows {
+                    let padding = " ".repeat(width - cmd.chars().count() + 2);
+                    shell_println!("{}{}{}", session.paint(Style::Command, &cmd), padding, description);
+                }
+            },
+            Some(_) => self.lookup(&path, words)?.print_long_help(session.theme())
+        }
+        Ok(())
+    }
+
+    /// The hidden `__complete` builtin the completion scripts call: prints the
+    /// candidates for the last word of `line`, one per line.
+    fn complete_builtin(&self, line: &str, session: &Session) {
+        let line = line.trim_end_matches(['\n', '\r']);
+        output::begin_capture();
+        let suggestions = self.get_suggestions(line, &session.path());
+        output::discard_capture();
+        for suggestion in suggestions.unwrap_or_default() {
+            shell_println!("{}", suggestion);
+        }
+    }
+
+    /// The `__docs` builtin: the man page, Markdown reference or a completion
+    /// script, printed or written to a file.
+    fn docs(&self, words: &[&str]) -> CommandResult<()> {
+        let kind = words.first().cloned().unwrap_or("");
+        let docs = match (kind, Shell::from_name(kind)) {
+            ("man", _) => self.man_page(),
+            ("markdown", _) => self.markdown_reference(),
+            (_, Some(shell)) => self.completion_script(shell),
+            _ => return Err("usage: __docs man|markdown|bash|zsh|fish [file]".to_owned())
+        };
+        match words.get(1) {
+            Some(file) => fs::write(file, docs).map_err(|e| format!("could not write {}: {}", file, e)),
+            None => {
+                shell_print!("{}", docs);
+                Ok(())
+            }
+        }
+    }
+
+    /// Commands reachable from the context at `path` that aren't below it,
+    /// split into global ones and ones inherited from ancestors.
+    fn outside_commands(&self, path: &[String]) -> (Vec<&Node<T>>, Vec<&Node<T>>) {
+        let refs = to_refs(path);
+        let mut globals: Vec<&Node<T>> = Vec::new();
+        let mut inherited: Vec<&Node<T>> = Vec::new();
+        let context = match self.find_node(&refs) {
+            Some(node) => node,
+            None => return (globals, inherited)
+        };
+        let mut seen: Vec<&str> = context.sub_nodes.iter()
+            .flat_map(|nodes| nodes.iter().map(|node| node.cmd.as_str()))
+            .collect();
+        if context.fallback {
+            for len in (0..refs.len()).rev() {
+                if let Some(nodes) = self.find_node(&refs[..len]).and_then(|parent| parent.sub_nodes.as_ref()) {
+                    for node in nodes.iter().filter(|node| !node.hidden) {
+                        if !seen.contains(&node.cmd.as_str()) {
+                            seen.push(&node.cmd);
+                            inherited.push(node);
+                        }
+                    }
+                }
+            }
+        }
+        for (_, node) in self.root.globals() {
+            if !seen.contains(&node.cmd.as_str()) {
+                seen.push(&node.cmd);
+                globals.push(node);
+            }
+        }
+        (globals, inherited)
+    }
+
+    fn print_outside_help(&self, path: &[String], theme: Option<&Theme>) {
+        let (globals, inherited) = self.outside_commands(path);
+        if !globals.is_empty() {
+            shell_println!("Global commands:");
+            for node in globals {
+                node.print_help(1, theme);
+            }
+        }
+        if !inherited.is_empty() {
+            shell_println!("Inherited commands:");
+            for node in inherited {
+                node.print_help(1, theme);
+            }
+        }
+    }
+
+    /// Finds the node at `path`, a list of command names from the root.
+    pub fn find_node(&self, path: &[&str]) -> Option<&Node<T>> {
+        let mut node = &self.root;
+        for cmd in path {
+            node = node.child(cmd)?;
         }
+        Some(node)
+    }
+
+    /// Node of the session's current context, `None` at the top level.
+    pub fn current_node(&self, session: &Session) -> Option<&Node<T>> {
+        session.current().and_then(|frame| self.find_node(&frame.path()))
     }
 
+    /// Drops contexts a callback pushed for commands that don't exist.
+    fn check_frames(&self, session: &mut Session) {
+        if let Some(pos) = session.frames.iter().position(|frame| self.find_node(&frame.path()).is_none()) {
+            output::error(&format!("no command '{}' to enter", session.frames[pos].path().join(" ")));
+            session.frames.truncate(pos);
+        }
+    }
 
-    pub fn run(&mut self)  {
+    pub(crate) fn gen_prompt(&self, session: &Session) -> String {
+        if let Some(ref renderer) = self.prompt {
+            return match self.context.lock() {
+                Ok(context) => renderer.render(&self.name, session, &context),
+                Err(_) => renderer.render(&self.name, session, &None)
+            };
+        }
+        let mut temp_prompt = self.name.clone();
+        for label in session.labels() {
+            temp_prompt = format!("{}/{}", temp_prompt, label);
+        }
+        format!("{} ", session.paint(Style::Prompt, &format!("{}>>", temp_prompt)))
+    }
+
+
+    /// Runs the interactive shell until `exit`/`quit` or Ctrl-D, then runs the exit
+    /// hooks and returns. History is saved when the editor is dropped.
+    pub fn run(&mut self) -> RunOutcome {
         println!("Welcome to {} v{}", self.name, self.version);
         // let config = Config::builder()
         //     .history_ignore_space(true)
         //     .completion_type(CompletionType::List)
         //     .build();
 
-        let c = TxCompleter::new(&self, None);
+        let c = TxCompleter::new(self, &[]);
         let mut rl: Editor<TxCompleter<T>> = Editor::new().history_ignore_space(true);
         rl.set_completer(Some(c));
 
         let home_dir = env::home_dir()
-            .and_then(|dir| {
-                Some(dir.to_str().unwrap_or("/tmp").to_owned())
-            })
+            .map(|dir| dir.to_str().unwrap_or("/tmp").to_owned())
             .unwrap();
         let history_file = format!("{}/{}", home_dir, ".txcli_history");
 
         if rl.load_history(&history_file).is_err() {
             println!("No previous history.");
         }
+        let mut rl = HistoryGuard { rl, history_file };
+        let mut session = self.new_session();
+        self.start();
 
-        let mut prompt = format!("\x1b[1;32m{}>>\x1b[0m ", self.name);
+        let mut prompt = self.gen_prompt(&session);
         let mut ops = 0u64;
-        let mut o_my_context: Option<Vec<(&Node<T>, String)>> = None;
-        let mut history: HashMap<String, String> = HashMap::new();
-        loop {
-            let mut line = match rl.readline(&prompt) {
+        let code = loop {
+            let line = match rl.rl.readline(&prompt) {
                 Ok(val) => val,
                 Err(ReadlineError::Interrupted) => continue,
-                Err(ReadlineError::Eof) => continue,
+                Err(ReadlineError::Eof) => {
+                    if session.pop().is_some() {
+                        rl.rl.set_completer(Some(TxCompleter::new(self, &session.path())));
+                        prompt = self.gen_prompt(&session);
+                        continue;
+                    }
+                    if !session.is_interactive() {
+                        break session.failure;
+                    }
+                    if self.exit_on_eof {
+                        println!();
+                        break 0;
+                    }
+                    continue;
+                },
                 Err(e) => {
-                    println!("Readline error: {}", e);
-                    break;
+                    output::error(&format!("readline failed: {}", e));
+                    break 1;
                 }
             };
 
             if !line.starts_with("profile") || line.contains("login") {
-                rl.add_history_entry(line.as_str());
+                rl.rl.add_history_entry(line.as_str());
             }
 
-            if line.contains('>') {
-                let v_line: Vec<String> = line.split('>').map(|e| e.to_owned()).collect();
-                if v_line.len() != 2 {
-                    println!("Wrong cmd format in line: {}", line);
-                    continue;
-                }
-                line = v_line[0].trim().to_owned();
-                let output = v_line[1].trim();
-                if output.contains(char::is_whitespace)  {
-                    println!("Wrong cmd format in output: {}", output);
-                    continue;
-                }
-                history.insert("tx_output_file".to_owned(), output.to_owned());
+            output::begin_line(&session);
+            let ok = self.execute(&line, &mut session);
+            output::end_line();
+            if let Some(code) = session.exit {
+                break code;
             }
-            else if line.contains('|') {
-                let v_line: Vec<String> = line.split('|').map(|e| e.to_owned()).collect();
-                if v_line.len() != 2 {
-                    println!("Wrong cmd format in line: {}", line);
-                    continue;
-                }
-                line = v_line[0].trim().to_owned();
-                let output = v_line[1].trim();
-                if output.contains(char::is_whitespace)  {
-                    println!("Wrong cmd format in output: {}", output);
-                    continue;
-                }
-                history.insert("tx_modifier".to_owned(), output.to_owned());
+            rl.rl.set_completer(Some(TxCompleter::new(self, &session.path())));
+            prompt = self.gen_prompt(&session);
+            if !ok {
+                continue;
             }
-            else {
-                history.remove("tx_modifier");
-                history.remove("tx_output_file");
+
+            if ops % 5 == 0 {
+                rl.save();
             }
+            ops += 1;
+        };
 
-            if line.contains('<') {
-                let v_line: Vec<String> = line.split('<').map(|e| e.to_owned()).collect();
-                if v_line.len() != 2 {
-                    println!("Wrong cmd format in line: {}", line);
-                    continue;
-                }
-                line = format!("{} <", v_line[0].trim());
-                let input = v_line[1].trim();
-                if input.contains(char::is_whitespace)  {
-                    println!("Wrong cmd format in input: {}", input);
-                    continue;
-                }
-                history.insert("tx_input_file".to_owned(), input.to_owned());
+        self.exit();
+        RunOutcome { code }
+    }
+
+    /// Runs the command given on the process command line and returns, e.g.
+    /// `my_cli hello darkness bob`, or starts the interactive shell when there is
+    /// none. `args` includes the program name, as from `std::env::args()`.
+    /// `--yes` and `--no-color` are taken as options before the command. The
+    /// words are the command's as they are, without expansion or redirects.
+    pub fn run_from_args<I>(&mut self, args: I) -> RunOutcome
+        where I: IntoIterator<Item = String>
+    {
+        let mut args = args.into_iter().skip(1).peekable();
+        while let Some(arg) = args.peek() {
+            match arg.as_str() {
+                "--yes" => self.assume_yes = true,
+                "--no-color" => self.color = Some(false),
+                _ => break
             }
-            else {
-                history.remove("tx_input_file");
+            args.next();
+        }
+        let words: Vec<String> = args.collect();
+        if words.is_empty() {
+            return self.run();
+        }
+        debug!("One-shot words: {:?}", words);
+
+        let mut session = self.new_session();
+        if words[0] == "__complete" {
+            // Called by the completion scripts, nothing but candidates may be printed.
+            let line = words[1..].iter()
+                .map(|word| if word.contains(char::is_whitespace) { format!("\"{}\"", word) } else { word.to_owned() })
+                .collect::<Vec<String>>()
+                .join(" ");
+            self.complete_builtin(&line, &session);
+            return RunOutcome { code: 0 };
+        }
+        let parsed = Line {
+            words: words.into_iter().map(|text| Token { text, quoted: false }).collect(),
+            ..Line::default()
+        };
+        self.start();
+        output::begin_line(&session);
+        self.execute_words(parsed, self.assume_yes, &mut session);
+        output::end_line();
+        let code = session.exit.unwrap_or(session.status);
+        self.exit();
+        RunOutcome { code }
+    }
+
+    /// Runs `line` and returns its output instead of printing it, used for `$(cmd)`.
+    /// Fails when the command does, its errors are printed as usual.
+    fn capture(&self, line: &str, session: &mut Session) -> CommandResult<String> {
+        output::begin_capture();
+        let ok = self.execute(line, session);
+        let mut captured = output::end_capture();
+        if !ok || session.status != 0 {
+            return Err(format!("'$({})' failed", line));
+        }
+        while captured.ends_with('\n') {
+            captured.pop();
+        }
+        Ok(captured)
+    }
+
+    /// Expands the words and redirect targets of `parsed` one at a time, so
+    /// whatever a variable holds stays in its word.
+    fn expand_line(&self, parsed: Line, vars: &Variables, session: &mut Session) -> CommandResult<Line> {
+        let mut expand = |word: String| variables::expand(&word, vars, |cmd| self.capture(cmd, session));
+        let mut words = Vec::with_capacity(parsed.words.len() + 1);
+        for word in parsed.words {
+            words.push(Token { text: expand(word.text)?, quoted: word.quoted });
+        }
+        Ok(Line {
+            words,
+            output_file: parsed.output_file.map(&mut expand).transpose()?,
+            modifier: parsed.modifier.map(&mut expand).transpose()?,
+            input_file: parsed.input_file.map(&mut expand).transpose()?
+        })
+    }
+
+    /// Runs `line` and remembers its status if it failed.
+    pub(crate) fn execute(&self, line: &str, session: &mut Session) -> bool {
+        let ok = self.execute_line(line, session);
+        if session.status != 0 {
+            session.failure = session.status;
+        }
+        ok
+    }
+
+    /// Expands, parses and dispatches a single command line. Returns false on
+    /// errors, or if the shell has exited and the line wasn't run.
+    fn execute_line(&self, line: &str, session: &mut Session) -> bool {
+        if session.exit.is_some() {
+            return false;
+        }
+        let mut vars = session.vars.clone();
+        vars.insert("?".to_owned(), session.status.to_string());
+        session.status = 0;
+        if let Some(words) = line.strip_prefix("__complete ") {
+            self.complete_builtin(words, session);
+            return true;
+        }
+        let mut parsed = match line::parse(line) {
+            Ok(parsed) => parsed,
+            Err(e) => {
+                output::error(&e);
+                return failed(session);
             }
+        };
+        let assume_yes = parsed.take_flag("--yes") || self.assume_yes;
+        match self.expand_line(parsed, &vars, session) {
+            Ok(parsed) => self.execute_words(parsed, assume_yes, session),
+            Err(e) => {
+                output::error(&e);
+                failed(session)
+            }
+        }
+    }
 
-            match line.trim() {
-                "exit" | "quit" => {
-                    if let Err(e) = rl.save_history(&history_file) {
-                        println!("Could not save history. Err: {}", e);
-                    }
-                    ::std::process::exit(0);
-                },
-                "top" => {
-                    rl.set_completer(Some(TxCompleter::new(&self, None)));
-                    o_my_context = None;
-                    history.clear();
-                    prompt = self.gen_prompt(&o_my_context);
-                    continue;
-                },
-                "up" => {
-                    if let Some(ref mut my_context) = o_my_context {
-                        my_context.pop();
-                        if let Some(&(context, _)) = my_context.last() {
-                            rl.set_completer(Some(TxCompleter::new(&self, Some(context))));
-                        }
-                        else {
-                            rl.set_completer(Some(TxCompleter::new(&self, None)));
-                        }
-                    }
-                    else {
-                        rl.set_completer(Some(TxCompleter::new(&self, None)));
-                        history.clear();
+    /// Runs an expanded line: the builtins, or the command its words lead to.
+    fn execute_words(&self, parsed: Line, assume_yes: bool, session: &mut Session) -> bool {
+        let mut words: Vec<String> = parsed.words.iter().map(|word| word.text.clone()).collect();
+
+        // Commands of the tree win over the builtins of the same name
+        let is_builtin = |name: &str| words.first().map(|word| word.as_str()) == Some(name)
+            && !self.has_command(session, name);
+        if is_builtin("set") || is_builtin("unset") || is_builtin("vars") {
+            if let Some(ok) = run_var_builtin(&parsed.words, session) {
+                return ok;
+            }
+        }
+        // `output=<format>` as the last word picks the format of the command's output
+        let format = match parsed.words.last() {
+            Some(word) if !word.quoted => word.text.strip_prefix("output=")
+                .filter(|name| Format::from_name(name).is_some())
+                .map(|name| name.to_owned()),
+            _ => None
+        };
+        if format.is_some() {
+            words.pop();
+        }
+
+        session.line.clear();
+        if let Some(output) = parsed.output_file {
+            session.line.insert("tx_output_file".to_owned(), output);
+        }
+        if let Some(modifier) = parsed.modifier {
+            session.line.insert("tx_modifier".to_owned(), modifier);
+        }
+        if let Some(input) = parsed.input_file {
+            session.line.insert("tx_input_file".to_owned(), input);
+            words.push("<".to_owned());
+        }
+
+        let first = words.first().map(|word| word.as_str());
+        if let Some("exit") | Some("quit") = first {
+            let code = match words.get(1) {
+                Some(code) => match code.parse() {
+                    Ok(code) => code,
+                    Err(_) => {
+                        output::error(&format!("exit: bad code '{}'", code));
+                        return failed(session);
                     }
-                    prompt = self.gen_prompt(&o_my_context);
-                    continue;
                 },
-                _ => ()
+                None if session.is_interactive() => 0,
+                None => session.failure
             };
+            session.exit = Some(code);
+            return true;
+        }
 
-            if line.starts_with("help") {
-                self.get_help();
-                continue;
+        if words.len() >= 2 && words[0] == "terminal" && words[1] == "length" && !self.has_command(session, "terminal") {
+            if words.len() > 3 {
+                output::error("usage: terminal length [lines|0|auto]");
+                return failed(session);
             }
-
-            let mut levels = Vec::new();
-            for (i, entry) in line.trim().split('"').enumerate() {
-                if i % 2 == 0 {
-                    if !entry.is_empty() {
-                        for token in entry.trim().split_whitespace() {
-                            if !token.is_empty() {
-                                levels.push(token);
-                            }
-                        }
+            match words.get(2).map(|length| length.as_str()) {
+                None => shell_println!("{}", session.page_length().map(|length| length.to_string()).unwrap_or_else(|| "auto".to_owned())),
+                Some("auto") => session.set_page_length(None),
+                Some(length) => match length.parse() {
+                    Ok(length) => session.set_page_length(Some(length)),
+                    Err(_) => {
+                        output::error(&format!("terminal length: bad length '{}'", length));
+                        return failed(session);
                     }
                 }
-                else {
-                    if !entry.is_empty() {
-                        levels.push(entry);
+            }
+            return true;
+        }
+
+        match words.as_slice() {
+            [top] if top == "top" => {
+                session.top();
+                return true;
+            },
+            [up] if up == "up" => {
+                session.pop();
+                return true;
+            },
+            _ => ()
+        };
+
+        if self.docs_builtin && first == Some("__docs") {
+            if let Err(e) = self.docs(&to_refs(&words[1..])) {
+                output::error(&e);
+                return failed(session);
+            }
+            return true;
+        }
+
+        if first == Some("help") {
+            if let Err(e) = self.help(&to_refs(&words[1..]), session) {
+                output::error(&e);
+                return failed(session);
+            }
+            return true;
+        }
+
+        let mut levels: Vec<&str> = words.iter().filter(|word| !word.is_empty()).map(|word| word.as_str()).collect();
+
+        let mut path: Vec<String> = session.path().iter().map(|cmd| (*cmd).to_owned()).collect();
+        if let Some(first) = levels.first().cloned() {
+            if is_path(first) {
+                match resolve_path(&path, first) {
+                    Ok((start, cmds)) => {
+                        path = start;
+                        levels.splice(0..1, cmds);
+                    },
+                    Err(e) => {
+                        output::error(&e);
+                        return failed(session);
                     }
                 }
             }
-
-            let mut node = &self.root;
-            if let Some(ref my_context) = o_my_context {
-                if let Some(&(context, _)) = my_context.last() {
-                    node = context;
+        }
+        if let Some(first) = levels.first() {
+            let local = self.find_node(&to_refs(&path)).and_then(|node| node.child(first));
+            if local.is_none() {
+                if let Some((parent, _)) = self.find_fallback(&path, first) {
+                    path = parent;
+                }
+            }
+        }
+        let mut node = {
+            let start = to_refs(&path);
+            match self.find_node(&start) {
+                Some(node) => node,
+                None => {
+                    output::error(&format!("command '{}' not found", start.join(" ")));
+                    return not_found(session);
                 }
             }
+        };
 
-            let mut i = 0;
-            let mut error = false;
-            while i != levels.len() {
-                debug!("i: {}, looking for: {}", i, levels[i]);
-                node = match node.find(levels[i]) {
-                    Some(current_node) => {
-                        if let Some(ref callback) = current_node.callback {
-                            let mut my_args: HashMap<String, &str> = HashMap::new();
-                            if let Some(ref args) = current_node.args {
-                                let required: Vec<&str> = args.iter().filter_map(|a| {
-                                    if a.1 {
-                                        Some(a.0.as_str())
-                                    }
-                                    else {
-                                        None
-                                    }
-                                }).collect();
-                                let optional: Vec<&str> = args.iter().filter_map(|a| {
-                                    if !a.1 {
-                                        Some(a.0.as_str())
-                                    }
-                                    else {
-                                        None
-                                    }
-                                }).collect();
-                                for (x, arg) in required.iter().enumerate() {
-                                    if let Some(val) = levels.get(i + 1) {
-                                        if *val == "?" {
-                                            current_node.print_help(1);
-                                            error = true;
-                                            break;
-                                        }
-                                        my_args.insert((*arg).to_owned(), val);
-                                    }
-                                    else {
-                                        println!("'{}' missing fields: {:?}", current_node.cmd, required[x..].to_vec());
+        let mut i = 0;
+        let mut error = false;
+        while i != levels.len() {
+            debug!("i: {}, looking for: {}", i, levels[i]);
+            node = match node.find(levels[i]) {
+                Some(current_node) => {
+                    path.push(current_node.cmd.to_owned());
+                    if levels.get(i + 1) == Some(&"?") {
+                        current_node.print_long_help(session.theme());
+                        break;
+                    }
+                    if let Some(ref callback) = current_node.callback {
+                        let mut my_args: HashMap<String, &str> = HashMap::new();
+                        if let Some(ref args) = current_node.args {
+                            let required: Vec<&str> = args.iter().filter_map(|a| {
+                                if a.required {
+                                    Some(a.name.as_str())
+                                }
+                                else {
+                                    None
+                                }
+                            }).collect();
+                            let optional: Vec<&str> = args.iter().filter_map(|a| {
+                                if !a.required {
+                                    Some(a.name.as_str())
+                                }
+                                else {
+                                    None
+                                }
+                            }).collect();
+                            for (x, arg) in required.iter().enumerate() {
+                                if let Some(val) = levels.get(i + 1) {
+                                    if *val == "?" {
+                                        current_node.print_help(1, session.theme());
                                         error = true;
                                         break;
                                     }
-                                    i += 1;
+                                    my_args.insert((*arg).to_owned(), val);
                                 }
-                                debug!("Levels: {:?}", levels);
-                                let start = i + 1;
-                                for level in &levels[start..] {
-                                    if level.contains('=') {
-                                        let cmd_val: Vec<&str> = level.splitn(2, '=').collect();
-                                        if !cmd_val.len() == 2 {
-                                            continue;
-                                        }
-                                        for arg in optional.iter() {
-                                            if arg == &cmd_val[0] {
-                                                my_args.insert((*arg).to_owned(), cmd_val[1]);
-                                                i += 1;
-                                            }
+                                else {
+                                    output::error(&format!("'{}' missing fields: {:?}", current_node.cmd, required[x..].to_vec()));
+                                    error = true;
+                                    break;
+                                }
+                                i += 1;
+                            }
+                            debug!("Levels: {:?}", levels);
+                            let start = i + 1;
+                            for level in &levels[start..] {
+                                if level.contains('=') {
+                                    let cmd_val: Vec<&str> = level.splitn(2, '=').collect();
+                                    if !cmd_val.len() == 2 {
+                                        continue;
+                                    }
+                                    for arg in optional.iter() {
+                                        if arg == &cmd_val[0] {
+                                            my_args.insert((*arg).to_owned(), cmd_val[1]);
+                                            i += 1;
                                         }
                                     }
                                 }
-
-                                if error {
-                                    break;
+                            }
+                            for arg in args.iter().filter(|a| !a.required) {
+                                if let Some(ref default) = arg.default {
+                                    my_args.entry(arg.name.clone()).or_insert(default.as_str());
                                 }
                             }
 
-                            debug!("Current: {:?}, args: {:?}, levels[i]: {:?}", current_node.cmd, my_args, levels.get(i + 1));
-                            if let Some(level) = levels.get(i + 1) {
-                                if !level.is_empty() {
-                                    i += 1;
-                                    continue;
-                                }
+                            if error {
+                                break;
                             }
-                            let mut context = match self.context.lock() {
-                                Ok(val) => val,
-                                Err(e) => {
-                                    println!("Could not lock context. Err: {}", e);
-                                    continue;
+                        }
+
+                        debug!("Current: {:?}, args: {:?}, levels[i]: {:?}", current_node.cmd, my_args, levels.get(i + 1));
+                        if levels.get(i + 1) == Some(&"?") {
+                            // `interface eth0 ?` asks about the command, it doesn't run it
+                            current_node.print_long_help(session.theme());
+                            break;
+                        }
+                        let more = matches!(levels.get(i + 1), Some(level) if !level.is_empty());
+                        if more && !current_node.context {
+                            // The command to run is further down the line
+                            node = current_node;
+                            i += 1;
+                            continue;
+                        }
+                        if let Some(ref name) = format {
+                            if current_node.args.iter().flatten().any(|a| a.name == "output") {
+                                // The command takes an `output` arg of its own
+                                my_args.insert("output".to_owned(), name.as_str());
+                            }
+                            else {
+                                session.line.insert("tx_output_format".to_owned(), name.clone());
+                            }
+                        }
+                        if let Some(format) = current_node.format {
+                            session.line.insert("tx_command_format".to_owned(), format.name().to_owned());
+                        }
+                        if let Some(ref msg) = current_node.confirm {
+                            if !assume_yes && !confirm(&current_node.cmd, msg, session) {
+                                error = true;
+                                break;
+                            }
+                        }
+                        let mut context = match self.context.lock() {
+                            Ok(val) => val,
+                            Err(e) => {
+                                output::error(&format!("could not lock context: {}", e));
+                                continue;
+                            }
+                        };
+                        let cmd_path: Vec<&str> = path.iter().map(|cmd| cmd.as_str()).collect();
+                        if let Err(e) = self.hooks.before(&cmd_path, &my_args, &mut context) {
+                            output::error(&e);
+                            error = true;
+                            break;
+                        }
+                        let args = my_args.clone();
+                        session.command = path.clone();
+                        let result = {
+                            let _sigint = SigintGuard::install();
+                            callback(my_args, &mut context, session)
+                        };
+                        self.hooks.after(&cmd_path, &args, &mut context, &result);
+                        self.check_frames(session);
+                        match result {
+                            Ok(Some(str_context)) => {
+                                if str_context == "up" {
+                                    session.pop();
+                                    break;
+                                };
+                                if str_context == "exit" {
+                                    session.exit = Some(0);
+                                    break;
                                 }
-                            };
-                            match callback(my_args, &mut context, &history) {
-                                Ok(Some(str_context)) => {
-                                    if str_context == "up" {
-                                        if let Some(ref mut my_context) = o_my_context {
-                                            my_context.pop();
-                                            if let Some(&(context, _)) = my_context.last() {
-                                                rl.set_completer(Some(TxCompleter::new(&self, Some(context))));
-                                            }
-                                            else {
-                                                rl.set_completer(Some(TxCompleter::new(&self, None)));
-                                            }
-                                        }
-                                        else {
-                                            rl.set_completer(Some(TxCompleter::new(&self, None)));
-                                            history.clear();
-                                        }
-                                        prompt = self.gen_prompt(&o_my_context);
+                                let (cmd, new_context) = match str_context.split_once(':') {
+                                    Some(split) => split,
+                                    None => {
+                                        output::error(&format!("'{}' returned '{}', contexts are entered with 'object:label'", current_node.cmd, str_context));
+                                        error = true;
                                         break;
-                                    };
-                                    let cmd_context: Vec<&str> = str_context.split(':').collect();
-                                    if cmd_context.len() < 2 {
-                                        panic!("Context switcher should return string 'object:name'");
-                                    }
-                                    let cmd = cmd_context[0];
-                                    let new_context = cmd_context[1];
-                                    debug!("new_context: {}", new_context);
-                                    if levels.get(i + 1).is_some() {
-                                        history.insert(current_node.cmd.to_owned(), new_context.to_owned());
-                                    }
-                                    else {
-                                        rl.set_completer(Some(TxCompleter::new(&self, Some(current_node))));
-                                        history.insert(cmd.to_owned(), new_context.to_owned());
-                                        if let Some(ref mut my_context) = o_my_context {
-                                            my_context.push((current_node, new_context.to_owned()));
-                                        }
-                                        else {
-                                            o_my_context = Some(vec![(current_node, new_context.to_owned())]);
-                                        }
                                     }
-                                },
-                                Err(e) => {
-                                    println!("Error: {}", e);
-                                },
-                                _ => ()
-                            }
+                                };
+                                debug!("new_context: {}", new_context);
+                                let frame = session.push_frame(Frame::new(path.clone(), new_context));
+                                frame.insert(cmd, new_context);
+                                // Only kept for the rest of the line when a command follows
+                                frame.line_only = more;
+                            },
+                            Err(e) => {
+                                output::error(&e);
+                                if session.status == 0 {
+                                    session.status = 1;
+                                }
+                                error = true;
+                                break;
+                            },
+                            _ => ()
                         }
-                        current_node
-                    },
-                    None => {
-                        if !levels[i].starts_with("?") {
-                            println!("Error: command '{}' not found", levels[i]);
-                            error = true;
+                    }
+                    current_node
+                },
+                None => {
+                    if !levels[i].starts_with("?") {
+                        output::error(&format!("command '{}' not found", levels[i]));
+                        session.status = NOT_FOUND;
+                        error = true;
+                    }
+                    else {
+                        node.print_help(1, session.theme());
+                        if i == 0 {
+                            self.print_outside_help(&path, session.theme());
                         }
-                        break;
                     }
-                };
-                i += 1;
-            }
-            if error {
-                continue;
+                    break;
+                }
+            };
+            i += 1;
+        }
+        session.frames.retain(|frame| !frame.line_only);
+        if let Some((depth, left)) = session.left.take() {
+            if session.frames.len() == depth {
+                session.frames.extend(left);
             }
+        }
+        if error && session.status == 0 {
+            session.status = 1;
+        }
+        !error
+    }
+}
 
-            if ops % 5 == 0 {
-                if let Err(e) = rl.save_history(&history_file) {
-                    println!("Could not save history. Err: {}", e);
+fn failed(session: &mut Session) -> bool {
+    session.status = 1;
+    false
+}
+
+fn not_found(session: &mut Session) -> bool {
+    session.status = NOT_FOUND;
+    false
+}
+
+fn to_refs(path: &[String]) -> Vec<&str> {
+    path.iter().map(|cmd| cmd.as_str()).collect()
+}
+
+/// Whether `token` starts from the root (`/cmd`) or a parent context (`../cmd`).
+pub fn is_path(token: &str) -> bool {
+    token.starts_with('/') || token == ".." || token.starts_with("../")
+}
+
+/// Resolves a path token against the context at `current`. Returns the command
+/// path to start from and the commands the token names,
+/// e.g. `../vlan` in `interface eth0` gives `([], ["vlan"])`.
+pub fn resolve_path<'l>(current: &[String], token: &'l str) -> Result<(Vec<String>, Vec<&'l str>), String> {
+    let mut start = current.to_vec();
+    let mut rest = token;
+    if let Some(stripped) = rest.strip_prefix('/') {
+        start.clear();
+        rest = stripped;
+    }
+    let mut cmds = Vec::new();
+    for part in rest.split('/') {
+        match part {
+            "" | "." => (),
+            ".." => {
+                if !cmds.is_empty() {
+                    return Err(format!("bad path '{}'", token));
+                }
+                if start.pop().is_none() {
+                    return Err("already at the top level".to_owned());
                 }
+            },
+            cmd => cmds.push(cmd)
+        }
+    }
+    Ok((start, cmds))
+}
+
+/// Handles `set`, `unset` and `vars`, returns `None` if `words` isn't one of
+/// them and whether it succeeded otherwise.
+fn run_var_builtin(words: &[Token], session: &mut Session) -> Option<bool> {
+    let cmd = words.first()?.text.as_str();
+    let args = &words[1..];
+    match (cmd, args.is_empty()) {
+        ("set", true) | ("vars", true) => {
+            let mut names: Vec<&String> = session.vars.keys().collect();
+            names.sort();
+            for name in names {
+                shell_println!("{} = {}", name, session.vars[name]);
             }
-            ops += 1;
-            prompt = self.gen_prompt(&o_my_context);
+        },
+        ("set", false) => {
+            match variables::parse_assignment(args) {
+                Ok((name, value)) => {
+                    session.vars.insert(name, value);
+                },
+                Err(e) => {
+                    output::error(&e);
+                    return Some(failed(session));
+                }
+            }
+        },
+        ("unset", true) => {
+            output::error("unset needs a variable name");
+            return Some(failed(session));
+        },
+        ("unset", false) => {
+            for name in args {
+                session.vars.remove(&name.text);
+            }
+        },
+        _ => return None
+    }
+    Some(true)
+}
+
+/// Whether stdin is a terminal, i.e. someone is typing the commands. Never
+/// the case for scripted input.
+pub fn is_interactive() -> bool {
+    unsafe { libc::isatty(libc::STDIN_FILENO) == 1 }
+}
+
+fn confirm(cmd: &str, msg: &str, session: &mut Session) -> bool {
+    if let Some(Some(yes)) = session.answers.as_mut().map(|answers| answers.pop_front()) {
+        if !yes {
+            output::warning("Aborted.");
         }
+        return yes;
+    }
+    if !session.is_interactive() {
+        output::error(&format!("'{}' needs confirmation, use --yes to run it non-interactively", cmd));
+        return false;
     }
+    print!("{} [y/N] ", msg);
+    let _ = io::stdout().flush();
+    let mut answer = String::new();
+    match io::stdin().read_line(&mut answer) {
+        Ok(_) => {
+            let answer = answer.trim().to_lowercase();
+            if answer == "y" || answer == "yes" {
+                return true;
+            }
+            output::warning("Aborted.");
+            false
+        },
+        Err(_) => false
+    }
+}
+
+/// Keeps the line editor of a running shell and saves its history when dropped,
+/// so it's written out however `run` returns.
+struct HistoryGuard<'a, T: 'a + Debug> {
+    rl: Editor<TxCompleter<'a, T>>,
+    history_file: String
 }
 
-pub fn exit_cli<T>(_args: HashMap<String, &str>, _: &mut Option<T>, _: &HashMap<String, String>) -> CommandResult<Option<String>> {
-    ::std::process::exit(0);
+impl <'a, T>HistoryGuard<'a, T>
+    where T: Debug
+{
+    fn save(&self) {
+        if let Err(e) = self.rl.save_history(&self.history_file) {
+            output::warning(&format!("Could not save history. Err: {}", e));
+        }
+    }
+}
+
+impl <'a, T>Drop for HistoryGuard<'a, T>
+    where T: Debug
+{
+    fn drop(&mut self) {
+        self.save();
+    }
+}
+
+/// Callback of the `exit` and `quit` nodes, asks `run` to return with 0. Typed
+/// on their own they're handled by the `exit [code]` builtin instead.
+pub fn exit_cli<T>(_args: HashMap<String, &str>, _: &mut Option<T>, _: &mut Session) -> CommandResult<Option<String>> {
+    Ok(Some("exit".to_owned()))
 }
 
 #[macro_export]
@@ -724,7 +1697,7 @@ macro_rules! shell_command_tree {
         [ $( $node:expr ),* ]
     ) => {
         {
-            let mut root_node = $crate::commands::Node::new(stringify!($name), $help, None);
+            let mut root_node = $crate::commands::Node::new(stringify!($name), None, $help, None);
             $(
                 root_node.add_node($node);
             )*
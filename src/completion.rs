@@ -1,24 +1,33 @@
+use std::cell::{Cell, RefCell};
 use std::fmt::Debug;
+use std::mem;
 use rustyline::line_buffer::LineBuffer;
 use rustyline::completion::Completer;
 use rustyline::Result;
 use super::CommandTree;
-use super::Node;
+use help::HelpEntry;
 
 pub struct TxCompleter<'a, T>
     where T: 'a + Debug
 {
     tree: &'a CommandTree<T>,
-    sub_node: Option<&'a Node<T>>
+    context: Vec<String>,
+    /// End of the word the last `complete` replaces.
+    end: Cell<usize>,
+    /// Candidate put in the line by the last `update`.
+    elected: RefCell<Option<String>>
 }
 
 impl <'a, T>TxCompleter<'a, T>
     where T: Debug
 {
-    pub fn new(tree: &'a CommandTree<T>, sub_node: Option<&'a Node<T>>) -> TxCompleter<'a, T> {
+    /// Completes commands typed in the context at `context`, a command path from the root.
+    pub fn new(tree: &'a CommandTree<T>, context: &[&str]) -> TxCompleter<'a, T> {
         TxCompleter {
-            tree: tree,
-            sub_node: sub_node
+            tree,
+            context: context.iter().map(|cmd| (*cmd).to_owned()).collect(),
+            end: Cell::new(0),
+            elected: RefCell::new(None)
         }
     }
 }
@@ -28,32 +37,219 @@ impl <'a, T>Completer for TxCompleter<'a, T>
 {
     fn complete(&self, line: &str, pos: usize) -> Result<(usize, Vec<String>)> {
         debug!("Completion on line: {}, pos: {}", line, pos);
-        let suggestions: Vec<String> = self.tree.get_suggestions(line, self.sub_node)
-            .and_then(|mut entries| {
-                Some(entries.drain(..).map(|entry| entry.to_owned()).collect())
-            })
-            .unwrap_or(Vec::new());
+        let context: Vec<&str> = self.context.iter().map(|cmd| cmd.as_str()).collect();
+        let completion = self.tree.completion(&context, line, pos);
+        self.end.set(completion.end);
+        *self.elected.borrow_mut() = None;
+        Ok((completion.start, completion.candidates))
+    }
 
-        Ok((pos, suggestions))
+    fn update(&self, line_buffer: &mut LineBuffer, start: usize, elected: &str) {
+        // Tab cycles through the candidates in the updated line, each replaces
+        // the one before it unless the original line was put back in between
+        let end = match *self.elected.borrow() {
+            Some(ref previous) if line_buffer.as_str().get(start..start + previous.len()) == Some(previous.as_str()) => start + previous.len(),
+            _ => self.end.get()
+        };
+        line_buffer.replace(start, end, elected);
+        *self.elected.borrow_mut() = Some(elected.to_owned());
     }
+}
 
-    fn update(&self, line_buffer: &mut LineBuffer, _start: usize, elected: &str) {
-        let line = line_buffer.as_str().to_owned();
+/// Candidates for the word under the cursor, each one replaces
+/// `line[start..end]`, the whole word, quotes included.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Completion {
+    pub start: usize,
+    pub end: usize,
+    pub candidates: Vec<String>
+}
 
-        let words: Vec<&str> = line.split(char::is_whitespace).collect();
+/// The word of a line the cursor is in, split the way the dispatcher does:
+/// on whitespace outside of double quotes.
+#[derive(Debug)]
+pub(crate) struct Word {
+    /// Words before it, unquoted.
+    pub before: Vec<String>,
+    /// Its text up to the cursor, unquoted.
+    pub prefix: String,
+    pub start: usize,
+    pub end: usize
+}
 
-        if let Some(last_word) = words.last() {
-            if elected.starts_with(last_word) {
-                let insert_pos = match line.rfind(char::is_whitespace) {
-                    Some(val) => val + 1,
-                    None => 0
-                };
-                debug!("Insert pos: {}, buf len: {}", insert_pos, line_buffer.len());
-                let end = line_buffer.pos();
-                line_buffer.replace(insert_pos, end, elected);
+impl Word {
+    pub fn at(line: &str, cursor: usize) -> Word {
+        let mut cursor = cursor.min(line.len());
+        while !line.is_char_boundary(cursor) {
+            cursor -= 1;
+        }
+        let mut before = Vec::new();
+        let mut word = String::new();
+        let mut start = None;
+        let mut quoted = false;
+        let mut chars = line[..cursor].char_indices().peekable();
+        while let Some((i, c)) = chars.next() {
+            if c.is_whitespace() && !quoted {
+                if start.take().is_some() {
+                    before.push(mem::take(&mut word));
+                }
+                continue;
+            }
+            if start.is_none() {
+                start = Some(i);
+            }
+            if c == '"' {
+                quoted = !quoted;
+            }
+            else if c == '\\' && quoted && chars.peek().map(|&(_, c)| c) == Some('"') {
+                chars.next();
+                word.push('"');
+            }
+            else {
+                word.push(c);
             }
         }
-        line_buffer.move_end();
+        let start = match start {
+            Some(start) => start,
+            // Nothing typed yet, a new word goes in at the cursor
+            None => return Word { before, prefix: word, start: cursor, end: cursor }
+        };
+        let mut end = line.len();
+        let mut chars = line[cursor..].char_indices();
+        while let Some((i, c)) = chars.next() {
+            if c == '\\' && quoted && line[cursor + i + 1..].starts_with('"') {
+                chars.next();
+            }
+            else if c == '"' {
+                quoted = !quoted;
+            }
+            else if c.is_whitespace() && !quoted {
+                end = cursor + i;
+                break;
+            }
+        }
+        Word {
+            before,
+            prefix: word,
+            start,
+            end
+        }
     }
+}
+
+/// Shells `CommandTree::completion_script` writes completion scripts for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish
+}
+
+impl Shell {
+    pub fn from_name(name: &str) -> Option<Shell> {
+        match name {
+            "bash" => Some(Shell::Bash),
+            "zsh" => Some(Shell::Zsh),
+            "fish" => Some(Shell::Fish),
+            _ => None
+        }
+    }
+}
+
+/// Completion script for running `name` with a command on its command line.
+/// Words right after a command path come from the tree: choices for the first
+/// required arg, or else sub commands and `name=` for optional args. Anything else is
+/// asked from the program itself through `name __complete <words>`.
+pub fn script(shell: Shell, name: &str, entry: &HelpEntry) -> String {
+    let mut table = Vec::new();
+    collect_words(entry, &mut Vec::new(), &mut table);
+    let func = format!("_{}", name.replace(|c: char| !c.is_alphanumeric(), "_"));
+    let mut out = String::new();
+    match shell {
+        Shell::Bash => {
+            out.push_str(&format!("# bash completion for {}\n", name));
+            out.push_str(&format!("{}() {{\n", func));
+            out.push_str("    local cur=\"${COMP_WORDS[COMP_CWORD]}\"\n");
+            out.push_str("    local path=\"${COMP_WORDS[*]:1:COMP_CWORD-1}\"\n");
+            out.push_str("    local candidates\n");
+            out.push_str("    case \"$path\" in\n");
+            for (path, words) in &table {
+                out.push_str(&format!("        {}) candidates={} ;;\n", quote(path), quote(&words.join(" "))));
+            }
+            out.push_str(&format!("        *) candidates=\"$({} __complete \"${{COMP_WORDS[@]:1:COMP_CWORD}}\" 2>/dev/null)\" ;;\n", name));
+            out.push_str("    esac\n");
+            out.push_str("    COMPREPLY=( $(compgen -W \"$candidates\" -- \"$cur\") )\n");
+            out.push_str("}\n");
+            out.push_str(&format!("complete -F {} {}\n", func, name));
+        },
+        Shell::Zsh => {
+            out.push_str(&format!("#compdef {}\n", name));
+            out.push_str(&format!("{}() {{\n", func));
+            out.push_str("    local -a candidates\n");
+            out.push_str("    case \"${words[2,CURRENT-1]}\" in\n");
+            for (path, words) in &table {
+                let words: Vec<String> = words.iter().map(|word| quote(word)).collect();
+                out.push_str(&format!("        {}) candidates=({}) ;;\n", quote(path), words.join(" ")));
+            }
+            out.push_str(&format!("        *) candidates=(${{(f)\"$({} __complete \"${{(@)words[2,CURRENT]}}\" 2>/dev/null)\"}}) ;;\n", name));
+            out.push_str("    esac\n");
+            out.push_str("    compadd -- $candidates\n");
+            out.push_str("}\n");
+            out.push_str(&format!("compdef {} {}\n", func, name));
+        },
+        Shell::Fish => {
+            out.push_str(&format!("# fish completion for {}\n", name));
+            out.push_str(&format!("function _{}_complete\n", func.trim_start_matches('_')));
+            out.push_str("    set -l words (commandline -opc)\n");
+            out.push_str("    set -e words[1]\n");
+            out.push_str("    switch (string join ' ' -- $words)\n");
+            for (path, words) in &table {
+                let words: Vec<String> = words.iter().map(|word| quote(word)).collect();
+                out.push_str(&format!("        case {}\n", quote(path)));
+                out.push_str(&format!("            printf '%s\\n' {}\n", words.join(" ")));
+            }
+            out.push_str("        case '*'\n");
+            out.push_str(&format!("            {} __complete $words (commandline -ct) 2>/dev/null\n", name));
+            out.push_str("    end\n");
+            out.push_str("end\n");
+            out.push_str(&format!("complete -c {} -f -a '(_{}_complete)'\n", name, func.trim_start_matches('_')));
+        }
+    }
+    out
+}
+
+/// Words that can follow each command path, for paths where any follow.
+fn collect_words(entry: &HelpEntry, path: &mut Vec<String>, table: &mut Vec<(String, Vec<String>)>) {
+    let words: Vec<String> = match entry.args.iter().find(|arg| arg.required) {
+        Some(arg) => arg.choices.clone(),
+        None => {
+            entry.visible_subcommands().iter().map(|sub| sub.name.clone())
+                .chain(entry.args.iter().map(|arg| format!("{}=", arg.name)))
+                .collect()
+        }
+    };
+    if !words.is_empty() {
+        table.push((path.join(" "), words));
+    }
+    for sub in entry.visible_subcommands() {
+        path.push(sub.name.clone());
+        collect_words(sub, path, table);
+        path.pop();
+    }
+}
+
+/// Puts a candidate in double quotes if the line would split it, take it for a
+/// redirect or expand it, escaping the quotes and `$` in it.
+pub(crate) fn quote_candidate(candidate: String) -> String {
+    if candidate.contains(|c: char| c.is_whitespace() || "\"$<>|".contains(c)) {
+        format!("\"{}\"", candidate.replace('"', "\\\"").replace('$', "\\$"))
+    }
+    else {
+        candidate
+    }
+}
 
+/// Single quotes `word` for sh-like shells.
+fn quote(word: &str) -> String {
+    format!("'{}'", word.replace('\'', "'\\''"))
 }
@@ -0,0 +1,150 @@
+use serde::Serialize;
+use serde_json::{self, Value};
+use serde_yaml;
+use commands::CommandResult;
+use table::Table;
+
+/// How `Session::output` renders structured values. Chosen per line with a
+/// trailing `output=json`, for the session with `set output json`, per command
+/// with `Node::set_output_format` or for the tree with
+/// `CommandTree::set_output_format`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Format {
+    #[default]
+    Text,
+    Json,
+    Yaml,
+    Table
+}
+
+impl Format {
+    pub fn from_name(name: &str) -> Option<Format> {
+        match name {
+            "text" => Some(Format::Text),
+            "json" => Some(Format::Json),
+            "yaml" => Some(Format::Yaml),
+            "table" => Some(Format::Table),
+            _ => None
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Format::Text => "text",
+            Format::Json => "json",
+            Format::Yaml => "yaml",
+            Format::Table => "table"
+        }
+    }
+}
+
+/// Renders `value` as `format`, ending with a newline.
+pub fn render<S: Serialize + ?Sized>(value: &S, format: Format) -> CommandResult<String> {
+    let mut out = match format {
+        Format::Json => serde_json::to_string_pretty(value).map_err(|e| e.to_string())?,
+        Format::Yaml => serde_yaml::to_string(value).map_err(|e| e.to_string())?,
+        Format::Text => {
+            let mut out = String::new();
+            text(&to_value(value)?, 0, &mut out);
+            out
+        },
+        Format::Table => {
+            match to_table(value)? {
+                Some(table) => table.render(),
+                None => scalar(&to_value(value)?)
+            }
+        }
+    };
+    if !out.ends_with('\n') {
+        out.push('\n');
+    }
+    Ok(out)
+}
+
+fn to_value<S: Serialize + ?Sized>(value: &S) -> CommandResult<Value> {
+    serde_json::to_value(value).map_err(|e| e.to_string())
+}
+
+/// A scalar as plain text, anything else as compact JSON.
+fn scalar(value: &Value) -> String {
+    match *value {
+        Value::Null => String::new(),
+        Value::String(ref s) => s.clone(),
+        ref other => other.to_string()
+    }
+}
+
+/// `key: value` lines, nested values indented below their key.
+fn text(value: &Value, indent: usize, out: &mut String) {
+    let pad = "  ".repeat(indent);
+    match *value {
+        Value::Object(ref map) => {
+            for (key, value) in map {
+                match *value {
+                    Value::Object(_) | Value::Array(_) => {
+                        out.push_str(&format!("{}{}:\n", pad, key));
+                        text(value, indent + 1, out);
+                    },
+                    ref value => out.push_str(&format!("{}{}: {}\n", pad, key, scalar(value)))
+                }
+            }
+        },
+        Value::Array(ref items) => {
+            for (i, item) in items.iter().enumerate() {
+                match *item {
+                    Value::Object(_) | Value::Array(_) => {
+                        if i > 0 {
+                            out.push('\n');
+                        }
+                        text(item, indent, out);
+                    },
+                    ref item => out.push_str(&format!("{}{}\n", pad, scalar(item)))
+                }
+            }
+        },
+        ref value => out.push_str(&format!("{}{}\n", pad, scalar(value)))
+    }
+}
+
+/// Rows of a list of objects under their keys, an object as key/value rows.
+/// Anything else doesn't make a table.
+pub fn to_table<S: Serialize + ?Sized>(value: &S) -> CommandResult<Option<Table>> {
+    let table = match to_value(value)? {
+        Value::Array(items) => {
+            let mut headers: Vec<String> = Vec::new();
+            for item in &items {
+                if let Value::Object(ref map) = *item {
+                    for key in map.keys() {
+                        if !headers.contains(key) {
+                            headers.push(key.clone());
+                        }
+                    }
+                }
+            }
+            if headers.is_empty() {
+                let mut table = Table::new(&["value"]);
+                for item in &items {
+                    table.add_row(&[scalar(item)]);
+                }
+                table
+            }
+            else {
+                let mut table = Table::new(&headers);
+                for item in &items {
+                    let row: Vec<String> = headers.iter().map(|key| item.get(key).map(scalar).unwrap_or_default()).collect();
+                    table.add_row(&row);
+                }
+                table
+            }
+        },
+        Value::Object(map) => {
+            let mut table = Table::new(&["key", "value"]);
+            for (key, value) in &map {
+                table.add_row(&[key.clone(), scalar(value)]);
+            }
+            table
+        },
+        _ => return Ok(None)
+    };
+    Ok(Some(table))
+}
@@ -0,0 +1,405 @@
+use serde_json;
+use commands::Arg;
+use theme::{self, Style, Theme};
+
+/// Help for a command and everything below it, built by `Node::help_entry`
+/// and turned into text by a `HelpRenderer`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct HelpEntry {
+    pub name: String,
+    pub description: String,
+    pub long_description: String,
+    pub examples: Vec<String>,
+    pub args: Vec<Arg>,
+    pub subcommands: Vec<HelpEntry>,
+    pub hidden: bool,
+    /// Running the command enters its context, where its subcommands run.
+    pub context: bool
+}
+
+impl HelpEntry {
+    /// `name <required> [optional=default]`
+    pub fn usage(&self) -> String {
+        let mut usage = self.name.clone();
+        for arg in self.args.iter().filter(|arg| arg.required) {
+            usage.push_str(&format!(" <{}>", arg.name));
+        }
+        for arg in self.args.iter().filter(|arg| !arg.required) {
+            match arg.default {
+                Some(ref default) => usage.push_str(&format!(" [{}={}]", arg.name, default)),
+                None => usage.push_str(&format!(" [{}]", arg.name))
+            }
+        }
+        usage
+    }
+
+    pub fn visible_subcommands(&self) -> Vec<&HelpEntry> {
+        self.subcommands.iter().filter(|entry| !entry.hidden).collect()
+    }
+
+    /// Visible commands below this entry whose name or descriptions contain
+    /// `keyword`, ignoring case, with their paths from this entry.
+    pub fn search(&self, keyword: &str) -> Vec<(Vec<&str>, &HelpEntry)> {
+        let keyword = keyword.to_lowercase();
+        let mut matches = Vec::new();
+        self.collect_matches(&keyword, &mut Vec::new(), &mut matches);
+        matches
+    }
+
+    fn collect_matches<'a>(&'a self, keyword: &str, path: &mut Vec<&'a str>, matches: &mut Vec<(Vec<&'a str>, &'a HelpEntry)>) {
+        for sub in self.visible_subcommands() {
+            path.push(&sub.name);
+            if sub.name.to_lowercase().contains(keyword)
+                || sub.description.to_lowercase().contains(keyword)
+                || sub.long_description.to_lowercase().contains(keyword) {
+                matches.push((path.clone(), sub));
+            }
+            sub.collect_matches(keyword, path, matches);
+            path.pop();
+        }
+    }
+}
+
+pub trait HelpRenderer {
+    fn render(&self, entry: &HelpEntry) -> String;
+}
+
+/// The classic `?` output, one indented `name:<tab>description` line per command.
+/// `level` 0 starts with a header for the entry itself.
+#[derive(Debug, Clone)]
+pub struct TextHelp {
+    pub level: usize,
+    /// How many levels of sub commands to show, all of them if `None`.
+    pub depth: Option<usize>,
+    /// Colors for command and arg names, none if `None`.
+    pub theme: Option<Theme>
+}
+
+impl TextHelp {
+    pub fn new(level: usize) -> TextHelp {
+        TextHelp { level, depth: None, theme: None }
+    }
+
+    pub fn depth(mut self, depth: usize) -> TextHelp {
+        self.depth = Some(depth);
+        self
+    }
+
+    /// Paints with `theme`, e.g. `session.theme()`.
+    pub fn theme(mut self, theme: Option<&Theme>) -> TextHelp {
+        self.theme = theme.cloned();
+        self
+    }
+
+    fn render_level(&self, entry: &HelpEntry, level: usize, out: &mut String) {
+        if entry.hidden {
+            return;
+        }
+        let theme = self.theme.as_ref();
+        if level > 0 {
+            for _ in 0..level {
+                out.push_str("  ");
+            }
+            out.push_str(&format!("{}:\t{}", theme::paint(theme, Style::Command, &entry.name), entry.description));
+            if !entry.args.is_empty() {
+                out.push_str(&format!(", Format: {} ", entry.name));
+                for arg in entry.args.iter().filter(|arg| arg.required) {
+                    out.push_str(&format!("<{}> ", theme::paint(theme, Style::Arg, &arg.name)));
+                }
+                for arg in entry.args.iter().filter(|arg| !arg.required) {
+                    out.push_str(&format!("[{}] ", theme::paint(theme, Style::Arg, &arg.name)));
+                }
+                if !entry.subcommands.is_empty() {
+                    out.push_str("[enter]");
+                }
+            }
+            out.push('\n');
+        }
+        else {
+            out.push_str(&format!("{}: {}\n", theme::paint(theme, Style::Command, &entry.name), entry.description));
+            out.push_str("-------------------\n");
+            out.push_str("Commands:\n");
+        }
+        if self.depth.map_or(false, |depth| level >= self.level + depth) {
+            return;
+        }
+        for sub in &entry.subcommands {
+            self.render_level(sub, level + 1, out);
+        }
+    }
+}
+
+impl HelpRenderer for TextHelp {
+    fn render(&self, entry: &HelpEntry) -> String {
+        let mut out = String::new();
+        self.render_level(entry, self.level, &mut out);
+        out
+    }
+}
+
+/// Everything about a single command, for `help <cmd>` and `<cmd> ?`:
+/// usage, long description, args, examples and its sub commands.
+#[derive(Debug, Clone, Default)]
+pub struct DetailHelp {
+    /// Colors for command and arg names, none if `None`.
+    pub theme: Option<Theme>
+}
+
+impl DetailHelp {
+    pub fn new() -> DetailHelp {
+        DetailHelp::default()
+    }
+
+    /// Paints with `theme`, e.g. `session.theme()`.
+    pub fn theme(mut self, theme: Option<&Theme>) -> DetailHelp {
+        self.theme = theme.cloned();
+        self
+    }
+}
+
+impl HelpRenderer for DetailHelp {
+    fn render(&self, entry: &HelpEntry) -> String {
+        let theme = self.theme.as_ref();
+        let mut out = theme::paint(theme, Style::Command, &entry.name);
+        if !entry.description.is_empty() {
+            out.push_str(&format!(" - {}", entry.description));
+        }
+        out.push_str(&format!("\n\nUsage: {}\n", entry.usage()));
+        if !entry.long_description.is_empty() {
+            out.push('\n');
+            for line in entry.long_description.lines() {
+                out.push_str(format!("  {}", line).trim_end());
+                out.push('\n');
+            }
+        }
+        if entry.context {
+            out.push_str("\nEnters a context, the commands below run inside it.\n");
+        }
+        if !entry.args.is_empty() {
+            out.push_str("\nArguments:\n");
+            let width = entry.args.iter().map(|arg| arg.name.chars().count()).max().unwrap_or(0);
+            for arg in &entry.args {
+                let mut line = format!("  {}{}", theme::paint(theme, Style::Arg, &arg.name), " ".repeat(width - arg.name.chars().count() + 2));
+                line.push_str(arg.help.as_deref().unwrap_or(""));
+                if !arg.required {
+                    match arg.default {
+                        Some(ref default) => line.push_str(&format!(" (optional, default: {})", default)),
+                        None => line.push_str(" (optional)")
+                    }
+                }
+                out.push_str(line.trim_end());
+                out.push('\n');
+            }
+        }
+        if !entry.examples.is_empty() {
+            out.push_str("\nExamples:\n");
+            for example in &entry.examples {
+                out.push_str(&format!("  {}\n", example));
+            }
+        }
+        if !entry.visible_subcommands().is_empty() {
+            out.push_str("\nCommands:\n");
+            for sub in &entry.subcommands {
+                out.push_str(&TextHelp::new(1).theme(theme).render(sub));
+            }
+        }
+        out
+    }
+}
+
+/// Usage and description in two aligned columns, for the entry's subcommands.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ColumnHelp;
+
+impl ColumnHelp {
+    fn collect<'a>(entry: &'a HelpEntry, level: usize, rows: &mut Vec<(String, &'a str)>) {
+        for sub in entry.visible_subcommands() {
+            rows.push((format!("{}{}", "  ".repeat(level), sub.usage()), &sub.description));
+            ColumnHelp::collect(sub, level + 1, rows);
+        }
+    }
+}
+
+impl HelpRenderer for ColumnHelp {
+    fn render(&self, entry: &HelpEntry) -> String {
+        let mut rows = Vec::new();
+        ColumnHelp::collect(entry, 1, &mut rows);
+        let width = rows.iter().map(|row| row.0.chars().count()).max().unwrap_or(0);
+        let mut out = String::new();
+        for (usage, description) in rows {
+            let padding = width - usage.chars().count() + 2;
+            out.push_str(format!("{}{}{}", usage, " ".repeat(padding), description).trim_end());
+            out.push('\n');
+        }
+        out
+    }
+}
+
+/// Markdown reference, one section per command with its full path.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MarkdownHelp;
+
+impl MarkdownHelp {
+    fn render_section(entry: &HelpEntry, path: &mut Vec<String>, out: &mut String) {
+        for sub in entry.visible_subcommands() {
+            path.push(sub.usage());
+            out.push_str(&format!("\n### `{}`\n\n", path.join(" ")));
+            if !sub.description.is_empty() {
+                out.push_str(&format!("{}\n", sub.description));
+            }
+            if !sub.long_description.is_empty() {
+                out.push_str(&format!("\n{}\n", sub.long_description));
+            }
+            if sub.context {
+                out.push_str("\nEnters a context, the commands below run inside it.\n");
+            }
+            if !sub.args.is_empty() {
+                out.push_str("\n| Argument | Required | Default | Description |\n");
+                out.push_str("| -------- | -------- | ------- | ----------- |\n");
+                for arg in &sub.args {
+                    out.push_str(&format!("| `{}` | {} | {} | {} |\n",
+                        arg.name,
+                        if arg.required { "yes" } else { "no" },
+                        arg.default.as_ref().map(|default| format!("`{}`", default)).unwrap_or_default(),
+                        arg.help.as_deref().unwrap_or("")));
+                }
+            }
+            if !sub.examples.is_empty() {
+                out.push_str("\nExamples:\n\n```\n");
+                for example in &sub.examples {
+                    out.push_str(&format!("{}\n", example));
+                }
+                out.push_str("```\n");
+            }
+            path.pop();
+            path.push(sub.name.clone());
+            MarkdownHelp::render_section(sub, path, out);
+            path.pop();
+        }
+    }
+}
+
+impl HelpRenderer for MarkdownHelp {
+    fn render(&self, entry: &HelpEntry) -> String {
+        let mut out = format!("## {}\n", entry.name);
+        if !entry.description.is_empty() {
+            out.push_str(&format!("\n{}\n", entry.description));
+        }
+        MarkdownHelp::render_section(entry, &mut Vec::new(), &mut out);
+        out
+    }
+}
+
+/// roff man page in section 1, one tagged paragraph per command with its full path.
+/// View it with `man -l`.
+#[derive(Debug, Clone)]
+pub struct ManHelp {
+    version: String
+}
+
+impl ManHelp {
+    pub fn new(version: &str) -> ManHelp {
+        ManHelp {
+            version: version.to_owned()
+        }
+    }
+
+    fn render_commands(entry: &HelpEntry, path: &mut Vec<String>, out: &mut String) {
+        for sub in entry.visible_subcommands() {
+            path.push(sub.name.clone());
+            out.push_str(&format!(".TP\n\\fB{}\\fR", roff_escape(&path.join(" "))));
+            for arg in sub.args.iter().filter(|arg| arg.required) {
+                out.push_str(&format!(" \\fI{}\\fR", roff_escape(&arg.name)));
+            }
+            for arg in sub.args.iter().filter(|arg| !arg.required) {
+                match arg.default {
+                    Some(ref default) => out.push_str(&format!(" [\\fI{}\\fR={}]", roff_escape(&arg.name), roff_escape(default))),
+                    None => out.push_str(&format!(" [\\fI{}\\fR]", roff_escape(&arg.name)))
+                }
+            }
+            out.push('\n');
+            out.push_str(&format!("{}\n", roff_text(&sub.description)));
+            if !sub.long_description.is_empty() || sub.context || !sub.args.is_empty() || !sub.examples.is_empty() {
+                out.push_str(".RS\n");
+                if !sub.long_description.is_empty() {
+                    out.push_str(&format!(".PP\n{}\n", roff_text(&sub.long_description)));
+                }
+                if sub.context {
+                    out.push_str(".PP\nEnters a context, the commands below run inside it.\n");
+                }
+                if !sub.args.is_empty() {
+                    out.push_str(".PP\n.B Arguments\n");
+                    for arg in &sub.args {
+                        out.push_str(&format!(".TP\n.I {}\n", roff_escape(&arg.name)));
+                        let mut text = arg.help.clone().unwrap_or_default();
+                        if !arg.required {
+                            match arg.default {
+                                Some(ref default) => text.push_str(&format!(" (optional, default: {})", default)),
+                                None => text.push_str(" (optional)")
+                            }
+                        }
+                        if !text.trim().is_empty() {
+                            out.push_str(&format!("{}\n", roff_text(text.trim())));
+                        }
+                    }
+                }
+                if !sub.examples.is_empty() {
+                    out.push_str(".PP\n.B Examples\n.PP\n.nf\n");
+                    for example in &sub.examples {
+                        out.push_str(&format!("{}\n", roff_text(example)));
+                    }
+                    out.push_str(".fi\n");
+                }
+                out.push_str(".RE\n");
+            }
+            ManHelp::render_commands(sub, path, out);
+            path.pop();
+        }
+    }
+}
+
+impl HelpRenderer for ManHelp {
+    fn render(&self, entry: &HelpEntry) -> String {
+        let mut out = format!(".TH \"{}\" 1 \"\" \"{} {}\"\n",
+            roff_escape(&entry.name.to_uppercase()), roff_escape(&entry.name), roff_escape(&self.version));
+        out.push_str(&format!(".SH NAME\n{} \\- {}\n", roff_escape(&entry.name), roff_escape(&entry.description)));
+        out.push_str(&format!(".SH SYNOPSIS\n.B {}\n", roff_escape(&entry.name)));
+        if !entry.long_description.is_empty() {
+            out.push_str(&format!(".SH DESCRIPTION\n{}\n", roff_text(&entry.long_description)));
+        }
+        out.push_str(".SH COMMANDS\n");
+        ManHelp::render_commands(entry, &mut Vec::new(), &mut out);
+        out
+    }
+}
+
+/// Escapes backslashes and dashes for roff.
+fn roff_escape(text: &str) -> String {
+    text.replace('\\', "\\e").replace('-', "\\-")
+}
+
+/// Escapes `text` and keeps its lines from being read as roff requests.
+fn roff_text(text: &str) -> String {
+    text.lines().map(|line| {
+        let line = roff_escape(line);
+        if line.starts_with('.') || line.starts_with('\'') {
+            format!("\\&{}", line)
+        }
+        else {
+            line
+        }
+    }).collect::<Vec<String>>().join("\n")
+}
+
+/// JSON with the whole entry tree, hidden commands included and flagged.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct JsonHelp;
+
+impl HelpRenderer for JsonHelp {
+    fn render(&self, entry: &HelpEntry) -> String {
+        // Strings, bools and lists only, serializing can't fail
+        let mut out = serde_json::to_string_pretty(entry).unwrap_or_default();
+        out.push('\n');
+        out
+    }
+}
@@ -0,0 +1,76 @@
+use std::collections::HashMap;
+use commands::CommandResult;
+
+pub type StartHook<T> = dyn Fn(&mut Option<T>);
+/// Gets the command path from the root and the parsed args. Returning an error
+/// stops the command from running.
+pub type BeforeHook<T> = dyn Fn(&[&str], &HashMap<String, &str>, &mut Option<T>) -> CommandResult<()>;
+pub type AfterHook<T> = dyn Fn(&[&str], &HashMap<String, &str>, &mut Option<T>, &CommandResult<Option<String>>);
+pub type ExitHook<T> = dyn Fn(&mut Option<T>);
+
+/// Callbacks run around the shell loop and around each command callback.
+pub struct Hooks<T> {
+    on_start: Vec<Box<StartHook<T>>>,
+    before_command: Vec<Box<BeforeHook<T>>>,
+    after_command: Vec<Box<AfterHook<T>>>,
+    on_exit: Vec<Box<ExitHook<T>>>
+}
+
+impl <T>Hooks<T> {
+    pub fn new() -> Hooks<T> {
+        Hooks {
+            on_start: Vec::new(),
+            before_command: Vec::new(),
+            after_command: Vec::new(),
+            on_exit: Vec::new()
+        }
+    }
+
+    pub fn add_on_start(&mut self, hook: Box<StartHook<T>>) {
+        self.on_start.push(hook);
+    }
+
+    pub fn add_before_command(&mut self, hook: Box<BeforeHook<T>>) {
+        self.before_command.push(hook);
+    }
+
+    pub fn add_after_command(&mut self, hook: Box<AfterHook<T>>) {
+        self.after_command.push(hook);
+    }
+
+    pub fn add_on_exit(&mut self, hook: Box<ExitHook<T>>) {
+        self.on_exit.push(hook);
+    }
+
+    pub fn start(&self, context: &mut Option<T>) {
+        for hook in &self.on_start {
+            hook(context);
+        }
+    }
+
+    /// Runs the before hooks in order, stopping at the first error.
+    pub fn before(&self, path: &[&str], args: &HashMap<String, &str>, context: &mut Option<T>) -> CommandResult<()> {
+        for hook in &self.before_command {
+            hook(path, args, context)?;
+        }
+        Ok(())
+    }
+
+    pub fn after(&self, path: &[&str], args: &HashMap<String, &str>, context: &mut Option<T>, result: &CommandResult<Option<String>>) {
+        for hook in &self.after_command {
+            hook(path, args, context, result);
+        }
+    }
+
+    pub fn exit(&self, context: &mut Option<T>) {
+        for hook in &self.on_exit {
+            hook(context);
+        }
+    }
+}
+
+impl <T>Default for Hooks<T> {
+    fn default() -> Hooks<T> {
+        Hooks::new()
+    }
+}
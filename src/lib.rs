@@ -1,8 +1,37 @@
 extern crate rustyline;
+extern crate libc;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde_json;
+extern crate serde_yaml;
 #[macro_use]
 extern crate log;
 
+pub mod theme;
+pub mod pager;
+#[macro_use]
+pub mod output;
 #[macro_use]
 pub mod commands;
 pub mod completion;
-pub use commands::{CommandTree, CommandResult, Node};
+pub mod variables;
+pub mod line;
+pub mod hooks;
+pub mod signal;
+pub mod session;
+pub mod prompt;
+pub mod help;
+pub mod format;
+pub mod table;
+pub mod testing;
+pub use commands::{Arg, CommandTree, CommandResult, Node, RunOutcome};
+pub use completion::{Completion, Shell};
+pub use format::Format;
+pub use table::{Align, Table};
+pub use pager::Pager;
+pub use help::{ColumnHelp, DetailHelp, HelpEntry, HelpRenderer, JsonHelp, ManHelp, MarkdownHelp, TextHelp};
+pub use signal::{cancel_token, CancelToken};
+pub use session::{Frame, Session};
+pub use prompt::{PromptFormat, PromptRenderer};
+pub use theme::{Style, Theme};
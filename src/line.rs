@@ -0,0 +1,153 @@
+use std::mem;
+
+/// A command line split into words, before expansion, and its redirects.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Line {
+    pub words: Vec<Token>,
+    /// Word after `>`.
+    pub output_file: Option<String>,
+    /// Word after `|`.
+    pub modifier: Option<String>,
+    /// Word after `<`.
+    pub input_file: Option<String>
+}
+
+/// A word of a line, `quoted` if any of it was in double quotes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Token {
+    pub text: String,
+    pub quoted: bool
+}
+
+impl Line {
+    /// Takes `flag` off the end of the line, if it's there and not quoted.
+    pub fn take_flag(&mut self, flag: &str) -> bool {
+        match self.words.last() {
+            Some(word) if !word.quoted && word.text == flag => {
+                self.words.pop();
+                true
+            },
+            _ => false
+        }
+    }
+}
+
+/// Splits `line` on whitespace outside of double quotes and takes out the
+/// `>`, `|` and `<` redirects. Quotes are dropped, `\"` is a quote inside
+/// them. `$(cmd)` stays in its word as is, whatever it contains, to be run
+/// when the word is expanded.
+pub fn parse(line: &str) -> Result<Line, String> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut in_word = false;
+    let mut quoted = false;
+    let mut was_quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                was_quoted = true;
+                in_word = true;
+            },
+            '\\' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                word.push('"');
+            },
+            '$' if chars.peek() == Some(&'(') => {
+                word.push(c);
+                in_word = true;
+                copy_capture(&mut chars, &mut word);
+            },
+            '>' | '|' | '<' if !quoted => {
+                if in_word {
+                    words.push(Part::Word(Token { text: mem::take(&mut word), quoted: was_quoted }));
+                    in_word = false;
+                    was_quoted = false;
+                }
+                words.push(Part::Redirect(c));
+            },
+            c if c.is_whitespace() && !quoted => {
+                if in_word {
+                    words.push(Part::Word(Token { text: mem::take(&mut word), quoted: was_quoted }));
+                    in_word = false;
+                    was_quoted = false;
+                }
+            },
+            c => {
+                word.push(c);
+                in_word = true;
+            }
+        }
+    }
+    if in_word {
+        words.push(Part::Word(Token { text: word, quoted: was_quoted }));
+    }
+
+    let mut parsed = Line::default();
+    let mut parts = words.into_iter();
+    let mut redirect = None;
+    let mut target = Vec::new();
+    loop {
+        match parts.next() {
+            Some(Part::Word(word)) if redirect.is_none() => parsed.words.push(word),
+            Some(Part::Word(word)) => target.push(word.text),
+            next => {
+                if let Some(op) = redirect.take() {
+                    set_redirect(&mut parsed, op, mem::take(&mut target), line)?;
+                }
+                match next {
+                    Some(Part::Redirect(op)) => redirect = Some(op),
+                    _ => break
+                }
+            }
+        }
+    }
+    Ok(parsed)
+}
+
+enum Part {
+    Word(Token),
+    Redirect(char)
+}
+
+fn set_redirect(parsed: &mut Line, op: char, target: Vec<String>, line: &str) -> Result<(), String> {
+    // One input and one output at most, `>` and `|` both being outputs
+    let taken = match op {
+        '<' => parsed.input_file.is_some(),
+        _ => parsed.output_file.is_some() || parsed.modifier.is_some()
+    };
+    if taken {
+        return Err(format!("wrong cmd format in line: {}", line));
+    }
+    let (slot, kind) = match op {
+        '>' => (&mut parsed.output_file, "output"),
+        '|' => (&mut parsed.modifier, "output"),
+        _ => (&mut parsed.input_file, "input")
+    };
+    if target.len() != 1 {
+        return Err(format!("wrong cmd format in {}: {}", kind, target.join(" ")));
+    }
+    *slot = target.into_iter().next();
+    Ok(())
+}
+
+/// Copies `(cmd)` into `word`, up to the matching `)`.
+fn copy_capture<I: Iterator<Item = char>>(chars: &mut I, word: &mut String) {
+    let mut depth = 0;
+    let mut quoted = false;
+    for c in chars {
+        word.push(c);
+        match c {
+            '"' => quoted = !quoted,
+            '(' if !quoted => depth += 1,
+            ')' if !quoted => {
+                depth -= 1;
+                if depth == 0 {
+                    return;
+                }
+            },
+            _ => ()
+        }
+    }
+}
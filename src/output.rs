@@ -0,0 +1,184 @@
+use std::cell::RefCell;
+use std::env;
+use std::io::{self, Write};
+use std::mem;
+use libc;
+use pager;
+use session::Session;
+use theme::{self, Style, Theme};
+
+thread_local! {
+    static CAPTURES: RefCell<Vec<Capture>> = const { RefCell::new(Vec::new()) };
+    static RECORDING: RefCell<Option<Recording>> = const { RefCell::new(None) };
+    /// Colors of the session running the current line, for errors and warnings.
+    static THEME: RefCell<Option<Theme>> = const { RefCell::new(None) };
+}
+
+/// Starts the output of a command line run in `session`: its pager and the
+/// colors of its errors.
+pub(crate) fn begin_line(session: &Session) {
+    THEME.with(|current| *current.borrow_mut() = session.theme().cloned());
+    pager::begin(&session.pager, session.page_length());
+}
+
+/// Ends the output of the line, showing what an external pager collected.
+pub(crate) fn end_line() {
+    pager::end();
+    THEME.with(|current| *current.borrow_mut() = None);
+}
+
+/// Output collected by `begin_capture`, errors and warnings are held apart
+/// and passed on when the capture ends.
+#[derive(Debug, Default)]
+struct Capture {
+    output: String,
+    errors: Vec<(Style, String)>
+}
+
+/// Everything commands printed while `testing::TestShell` drives them: output,
+/// errors and both in the order they came.
+#[derive(Debug, Default)]
+pub(crate) struct Recording {
+    pub output: String,
+    pub errors: String,
+    pub transcript: String
+}
+
+pub(crate) fn begin_recording() {
+    RECORDING.with(|recording| *recording.borrow_mut() = Some(Recording::default()));
+}
+
+pub(crate) fn end_recording() -> Recording {
+    RECORDING.with(|recording| recording.borrow_mut().take()).unwrap_or_default()
+}
+
+pub(crate) fn is_recording() -> bool {
+    RECORDING.with(|recording| recording.borrow().is_some())
+}
+
+fn record(text: &str, error: bool) -> bool {
+    RECORDING.with(|recording| {
+        match *recording.borrow_mut() {
+            Some(ref mut recording) => {
+                if error {
+                    recording.errors.push_str(text);
+                }
+                else {
+                    recording.output.push_str(text);
+                }
+                recording.transcript.push_str(text);
+                true
+            },
+            None => false
+        }
+    })
+}
+
+/// Writes command output. It goes to stdout, through the pager if one is on,
+/// unless a capture is active, in which case it's collected for the innermost
+/// one, e.g. for `$(cmd)`.
+pub fn write(text: &str) {
+    let captured = CAPTURES.with(|captures| {
+        if let Some(capture) = captures.borrow_mut().last_mut() {
+            capture.output.push_str(text);
+            true
+        }
+        else {
+            false
+        }
+    });
+    if !captured && !record(text, false) && !pager::write(text) {
+        print!("{}", text);
+        let _ = io::stdout().flush();
+    }
+}
+
+/// Prints an error message to stderr, or along with the output while it's paged.
+/// Errors aren't command output: a capture holds them until it ends instead of
+/// adding them to what it returns.
+pub fn error(msg: &str) {
+    notice(Style::Error, format!("Error: {}", msg));
+}
+
+pub fn warning(msg: &str) {
+    notice(Style::Warning, msg.to_owned());
+}
+
+fn notice(style: Style, msg: String) {
+    let msg = CAPTURES.with(|captures| {
+        match captures.borrow_mut().last_mut() {
+            Some(capture) => {
+                capture.errors.push((style, msg));
+                None
+            },
+            None => Some(msg)
+        }
+    });
+    if let Some(msg) = msg {
+        if record(&format!("{}\n", msg), true) {
+            return;
+        }
+        let line = THEME.with(|current| format!("{}\n", theme::paint(current.borrow().as_ref(), style, &msg)));
+        // Kept in order with the output on a paged terminal
+        if !pager::write(&line) {
+            eprint!("{}", line);
+        }
+    }
+}
+
+pub fn begin_capture() {
+    CAPTURES.with(|captures| captures.borrow_mut().push(Capture::default()));
+}
+
+/// Returns the output collected since `begin_capture`, the errors and warnings
+/// it held are printed now.
+pub fn end_capture() -> String {
+    let capture = CAPTURES.with(|captures| captures.borrow_mut().pop()).unwrap_or_default();
+    for (style, msg) in capture.errors {
+        notice(style, msg);
+    }
+    capture.output
+}
+
+/// Ends a capture, dropping its errors and warnings along with the output.
+pub(crate) fn discard_capture() {
+    CAPTURES.with(|captures| captures.borrow_mut().pop());
+}
+
+/// Columns and rows of the terminal stdout goes to, `None` if it's not one.
+/// Falls back to `COLUMNS` and `LINES` if the terminal doesn't tell. Always
+/// `None` under `testing::TestShell`, so output doesn't depend on the terminal.
+pub fn terminal_size() -> Option<(usize, usize)> {
+    if is_recording() {
+        return None;
+    }
+    unsafe {
+        if libc::isatty(libc::STDOUT_FILENO) != 1 {
+            return None;
+        }
+        let mut size: libc::winsize = mem::zeroed();
+        if libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) == 0 && size.ws_col > 0 && size.ws_row > 0 {
+            return Some((size.ws_col as usize, size.ws_row as usize));
+        }
+    }
+    let columns = env::var("COLUMNS").ok().and_then(|columns| columns.parse().ok());
+    let lines = env::var("LINES").ok().and_then(|lines| lines.parse().ok());
+    Some((columns.unwrap_or(80), lines.unwrap_or(24)))
+}
+
+#[macro_export]
+macro_rules! shell_print {
+    ($($arg:tt)*) => {
+        $crate::output::write(&format!($($arg)*))
+    };
+}
+
+#[macro_export]
+macro_rules! shell_println {
+    () => {
+        $crate::output::write("\n")
+    };
+    ($($arg:tt)*) => {
+        $crate::output::write(&format!("{}\n", format_args!($($arg)*)))
+    };
+}
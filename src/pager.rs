@@ -0,0 +1,193 @@
+use std::cell::RefCell;
+use std::env;
+use std::io::{self, Write};
+use std::mem;
+use std::process::{Command, Stdio};
+use libc;
+use output;
+use signal;
+
+/// How output longer than the terminal is shown.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum Pager {
+    /// Everything is printed as it comes.
+    Off,
+    /// `--More--` after every screen: space shows the next one, enter the next
+    /// line and `q` drops the rest of the output and cancels the command.
+    #[default]
+    More,
+    /// Output is collected and piped into this shell command, e.g. `less -R`.
+    Command(String)
+}
+
+impl Pager {
+    /// `$PAGER` if it's set, `More` otherwise. Opt in with
+    /// `tree.set_pager(Pager::from_env())`.
+    pub fn from_env() -> Pager {
+        match env::var("PAGER") {
+            Ok(ref cmd) if !cmd.trim().is_empty() => Pager::Command(cmd.to_owned()),
+            _ => Pager::More
+        }
+    }
+}
+
+/// Paging of the command line that's running.
+struct State {
+    /// External pager the output is collected for.
+    command: Option<String>,
+    page: Option<usize>,
+    lines: usize,
+    quit: bool,
+    buffer: Option<String>
+}
+
+thread_local! {
+    static STATE: RefCell<State> = const { RefCell::new(State {
+        command: None,
+        page: None,
+        lines: 0,
+        quit: false,
+        buffer: None
+    }) };
+}
+
+/// Starts paging the output of a command line with `pager`, if paging is on
+/// and both ends are a terminal. `length` is the session's page length, see
+/// `Session::set_page_length`.
+pub(crate) fn begin(pager: &Pager, length: Option<usize>) {
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        state.lines = 0;
+        state.quit = false;
+        state.page = None;
+        state.buffer = None;
+        state.command = None;
+        if *pager == Pager::Off || length == Some(0) {
+            return;
+        }
+        let interactive = unsafe { libc::isatty(libc::STDIN_FILENO) == 1 };
+        let rows = match output::terminal_size() {
+            Some((_, rows)) if interactive => rows,
+            _ => return
+        };
+        // One line is left for the --More-- prompt
+        state.page = Some(length.unwrap_or(rows).saturating_sub(1).max(1));
+        if let Pager::Command(ref cmd) = *pager {
+            state.command = Some(cmd.clone());
+            state.buffer = Some(String::new());
+        }
+    });
+}
+
+/// Ends paging, output collected for an external pager is shown now.
+pub(crate) fn end() {
+    let pending = STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        let buffer = state.buffer.take();
+        let page = state.page.take();
+        match (buffer, page, state.command.take()) {
+            (Some(text), Some(page), Some(cmd)) => Some((text, page, cmd)),
+            _ => None
+        }
+    });
+    if let Some((text, page, cmd)) = pending {
+        if text.lines().count() <= page || run_pager(&cmd, &text).is_err() {
+            print!("{}", text);
+            let _ = io::stdout().flush();
+        }
+    }
+}
+
+/// Takes `text` if a command's output is being paged. Returns false if it
+/// should be printed as is.
+pub(crate) fn write(text: &str) -> bool {
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        let page = match state.page {
+            Some(page) => page,
+            None => return false
+        };
+        if let Some(ref mut buffer) = state.buffer {
+            buffer.push_str(text);
+            return true;
+        }
+        let mut stdout = io::stdout();
+        for line in text.split_inclusive('\n') {
+            if state.quit {
+                break;
+            }
+            let _ = stdout.write_all(line.as_bytes());
+            if line.ends_with('\n') {
+                state.lines += 1;
+                if state.lines >= page {
+                    let _ = stdout.flush();
+                    match more() {
+                        Key::Page => state.lines = 0,
+                        Key::Line => state.lines = page - 1,
+                        Key::Quit => {
+                            state.quit = true;
+                            signal::cancel();
+                        }
+                    }
+                }
+            }
+        }
+        let _ = stdout.flush();
+        true
+    })
+}
+
+enum Key {
+    Page,
+    Line,
+    Quit
+}
+
+/// Shows `--More--` and waits for a key, without echo or enter.
+fn more() -> Key {
+    const PROMPT: &str = "--More--";
+    print!("{}", PROMPT);
+    let _ = io::stdout().flush();
+    let key = read_key();
+    print!("\r{}\r", " ".repeat(PROMPT.len()));
+    let _ = io::stdout().flush();
+    match key {
+        Some(b' ') => Key::Page,
+        Some(b'\n') | Some(b'\r') => Key::Line,
+        Some(b'q') | Some(b'Q') | None => Key::Quit,
+        Some(_) => Key::Page
+    }
+}
+
+fn read_key() -> Option<u8> {
+    unsafe {
+        let mut saved: libc::termios = mem::zeroed();
+        if libc::tcgetattr(libc::STDIN_FILENO, &mut saved) != 0 {
+            return None;
+        }
+        let mut raw = saved;
+        raw.c_lflag &= !(libc::ICANON | libc::ECHO);
+        raw.c_cc[libc::VMIN] = 1;
+        raw.c_cc[libc::VTIME] = 0;
+        libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &raw);
+        let mut key = 0u8;
+        let read = libc::read(libc::STDIN_FILENO, &mut key as *mut u8 as *mut libc::c_void, 1);
+        libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &saved);
+        if read == 1 {
+            Some(key)
+        }
+        else {
+            None
+        }
+    }
+}
+
+fn run_pager(cmd: &str, text: &str) -> io::Result<()> {
+    let mut child = Command::new("sh").arg("-c").arg(cmd).stdin(Stdio::piped()).spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        // The pager may quit before reading everything
+        let _ = stdin.write_all(text.as_bytes());
+    }
+    child.wait()?;
+    Ok(())
+}
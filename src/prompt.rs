@@ -0,0 +1,71 @@
+use session::Session;
+
+/// Builds the prompt shown before each line. Implemented for closures taking
+/// the tree name, the session and the context, and for `PromptFormat`.
+pub trait PromptRenderer<T> {
+    fn render(&self, name: &str, session: &Session, context: &Option<T>) -> String;
+}
+
+impl <T, F>PromptRenderer<T> for F
+    where F: Fn(&str, &Session, &Option<T>) -> String
+{
+    fn render(&self, name: &str, session: &Session, context: &Option<T>) -> String {
+        self(name, session, context)
+    }
+}
+
+/// Prompt from a format string with the tokens:
+///
+/// Token | Value
+/// ----- | -----
+/// `%n`  | tree name
+/// `%c`  | context labels joined with `/`, e.g. `eth0/10`
+/// `%p`  | tree name followed by the context labels, e.g. `mycli/eth0/10`
+/// `%s`  | status of the last command
+/// `%m`  | session mode, see `Session::set_mode`
+/// `%%`  | a literal `%`
+#[derive(Debug, Clone)]
+pub struct PromptFormat {
+    format: String
+}
+
+impl PromptFormat {
+    pub fn new(format: &str) -> PromptFormat {
+        PromptFormat {
+            format: format.to_owned()
+        }
+    }
+}
+
+impl <T>PromptRenderer<T> for PromptFormat {
+    fn render(&self, name: &str, session: &Session, _context: &Option<T>) -> String {
+        let mut prompt = String::with_capacity(self.format.len());
+        let mut chars = self.format.chars();
+        while let Some(c) = chars.next() {
+            if c != '%' {
+                prompt.push(c);
+                continue;
+            }
+            match chars.next() {
+                Some('n') => prompt.push_str(name),
+                Some('c') => prompt.push_str(&session.labels().join("/")),
+                Some('p') => {
+                    prompt.push_str(name);
+                    for label in session.labels() {
+                        prompt.push('/');
+                        prompt.push_str(label);
+                    }
+                },
+                Some('s') => prompt.push_str(&session.status().to_string()),
+                Some('m') => prompt.push_str(session.mode()),
+                Some('%') => prompt.push('%'),
+                Some(other) => {
+                    prompt.push('%');
+                    prompt.push(other);
+                },
+                None => prompt.push('%')
+            }
+        }
+        prompt
+    }
+}
@@ -0,0 +1,326 @@
+use std::any::Any;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use serde::Serialize;
+use commands::{self, CommandResult};
+use format::{self, Format};
+use output;
+use pager::Pager;
+use signal::{self, CancelToken};
+use table::Table;
+use theme::{self, Style, Theme};
+use variables::{self, Variables};
+
+/// One entered context, e.g. `interface eth0`. Owns the values set while
+/// entering it, which go away with it on `up`.
+pub struct Frame {
+    path: Vec<String>,
+    label: String,
+    data: HashMap<String, String>,
+    payload: Option<Box<dyn Any>>,
+    /// Entered on the way to a command further down the same line, e.g.
+    /// `eth0` in `interface eth0 mtu 9000`. Left again once the line is done.
+    pub(crate) line_only: bool
+}
+
+impl Frame {
+    pub fn new(path: Vec<String>, label: &str) -> Frame {
+        Frame {
+            path,
+            label: label.to_owned(),
+            data: HashMap::new(),
+            payload: None,
+            line_only: false
+        }
+    }
+
+    /// Command path of the context node, from the root.
+    pub fn path(&self) -> Vec<&str> {
+        self.path.iter().map(|cmd| cmd.as_str()).collect()
+    }
+
+    /// Name of the command that entered the context.
+    pub fn cmd(&self) -> &str {
+        self.path.last().map(|cmd| cmd.as_str()).unwrap_or("")
+    }
+
+    /// Value the context was entered with, shown in the prompt.
+    pub fn label(&self) -> &str {
+        &self.label
+    }
+
+    pub fn get(&self, key: &str) -> Option<&String> {
+        self.data.get(key)
+    }
+
+    pub fn insert(&mut self, key: &str, value: &str) {
+        self.data.insert(key.to_owned(), value.to_owned());
+    }
+
+    pub fn data(&self) -> &HashMap<String, String> {
+        &self.data
+    }
+
+    /// Attaches a typed value to the context, e.g. a handle on the selected interface.
+    pub fn set_payload<P: Any>(&mut self, payload: P) {
+        self.payload = Some(Box::new(payload));
+    }
+
+    pub fn payload<P: Any>(&self) -> Option<&P> {
+        self.payload.as_ref().and_then(|payload| payload.downcast_ref())
+    }
+
+    pub fn payload_mut<P: Any>(&mut self) -> Option<&mut P> {
+        self.payload.as_mut().and_then(|payload| payload.downcast_mut())
+    }
+}
+
+impl fmt::Debug for Frame {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Frame")
+            .field("path", &self.path)
+            .field("label", &self.label)
+            .field("data", &self.data)
+            .field("payload", &self.payload.is_some())
+            .field("line_only", &self.line_only)
+            .finish()
+    }
+}
+
+/// State of a running shell: the context stack and the shell variables.
+/// Handed to every callback.
+#[derive(Debug, Default)]
+pub struct Session {
+    pub(crate) frames: Vec<Frame>,
+    pub(crate) line: HashMap<String, String>,
+    pub(crate) command: Vec<String>,
+    pub(crate) vars: Variables,
+    pub(crate) status: i32,
+    pub(crate) failure: i32,
+    pub(crate) mode: String,
+    pub(crate) format: Format,
+    /// Colors of prompts, help and errors, `None` when they're off.
+    pub(crate) theme: Option<Theme>,
+    pub(crate) pager: Pager,
+    pub(crate) page_length: Option<usize>,
+    pub(crate) exit: Option<i32>,
+    /// Contexts left for one reached by path, e.g. `/interface eth1` from
+    /// inside `eth0`, with the depth they were left at. Put back if the line
+    /// only passed through.
+    pub(crate) left: Option<(usize, Vec<Frame>)>,
+    /// Answers to confirmation prompts when driven by `testing::TestShell`,
+    /// which never reads from the terminal.
+    pub(crate) answers: Option<VecDeque<bool>>
+}
+
+impl Session {
+    pub fn new() -> Session {
+        Session::default()
+    }
+
+    pub fn frames(&self) -> &[Frame] {
+        &self.frames
+    }
+
+    pub fn current(&self) -> Option<&Frame> {
+        self.frames.last()
+    }
+
+    pub fn current_mut(&mut self) -> Option<&mut Frame> {
+        self.frames.last_mut()
+    }
+
+    /// Whether someone is typing the commands, never under `testing::TestShell`.
+    pub fn is_interactive(&self) -> bool {
+        self.answers.is_none() && commands::is_interactive()
+    }
+
+    pub fn depth(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Command path of the current context, empty at the top level.
+    pub fn path(&self) -> Vec<&str> {
+        self.current().map(|frame| frame.path()).unwrap_or_default()
+    }
+
+    /// Labels of all entered contexts, outermost first, e.g. `["eth0", "10"]`.
+    pub fn labels(&self) -> Vec<&str> {
+        self.frames.iter().map(|frame| frame.label()).collect()
+    }
+
+    /// Command path of the running callback, from the root.
+    pub fn command_path(&self) -> Vec<&str> {
+        self.command.iter().map(|cmd| cmd.as_str()).collect()
+    }
+
+    /// Enters the context of `cmd`, a command (or space separated path) below the
+    /// current context. The shell drops it again if no such command exists.
+    pub fn push(&mut self, cmd: &str, label: &str) -> &mut Frame {
+        let mut path: Vec<String> = self.path().iter().map(|cmd| (*cmd).to_owned()).collect();
+        path.extend(cmd.split_whitespace().map(|cmd| cmd.to_owned()));
+        self.push_frame(Frame::new(path, label))
+    }
+
+    /// Enters the context of the running command, the same as returning
+    /// `"object:label"` but gives access to the new frame.
+    pub fn enter(&mut self, label: &str) -> &mut Frame {
+        let path = self.command.clone();
+        self.push_frame(Frame::new(path, label))
+    }
+
+    /// Pushes `frame` on top of the contexts it's below, leaving the others.
+    pub(crate) fn push_frame(&mut self, frame: Frame) -> &mut Frame {
+        let keep = self.frames.iter()
+            .take_while(|outer| outer.path.len() < frame.path.len() && frame.path.starts_with(&outer.path))
+            .count();
+        if keep < self.frames.len() {
+            let left = self.frames.split_off(keep);
+            let (depth, frames) = self.left.get_or_insert((keep, Vec::new()));
+            *depth = keep.min(*depth);
+            frames.extend(left.into_iter().filter(|frame| !frame.line_only));
+        }
+        self.frames.push(frame);
+        self.frames.last_mut().unwrap()
+    }
+
+    /// Leaves the current context, same as `up`.
+    pub fn pop(&mut self) -> Option<Frame> {
+        self.frames.pop()
+    }
+
+    /// Leaves all contexts, same as `top`.
+    pub fn top(&mut self) {
+        self.frames.clear();
+    }
+
+    /// Looks `key` up in the values of the current line (`tx_modifier`,
+    /// `tx_output_file`, `tx_input_file`), then in the contexts from the
+    /// innermost to the outermost.
+    pub fn get(&self, key: &str) -> Option<&String> {
+        self.line.get(key)
+            .or_else(|| self.frames.iter().rev().filter_map(|frame| frame.get(key)).next())
+    }
+
+    /// Innermost context payload of type `P`.
+    pub fn payload<P: Any>(&self) -> Option<&P> {
+        self.frames.iter().rev().filter_map(|frame| frame.payload()).next()
+    }
+
+    /// Modifier after `|` on the current line.
+    pub fn modifier(&self) -> Option<&str> {
+        self.line.get("tx_modifier").map(|val| val.as_str())
+    }
+
+    /// File after `>` on the current line.
+    pub fn output_file(&self) -> Option<&str> {
+        self.line.get("tx_output_file").map(|val| val.as_str())
+    }
+
+    /// File after `<` on the current line.
+    pub fn input_file(&self) -> Option<&str> {
+        self.line.get("tx_input_file").map(|val| val.as_str())
+    }
+
+    pub fn var(&self, name: &str) -> Option<String> {
+        variables::lookup(&self.vars, name)
+    }
+
+    pub fn set_var(&mut self, name: &str, value: &str) {
+        self.vars.insert(name.to_owned(), value.to_owned());
+    }
+
+    pub fn vars(&self) -> &Variables {
+        &self.vars
+    }
+
+    /// Status of the last command, 0 if it succeeded, 127 if it wasn't found.
+    pub fn status(&self) -> i32 {
+        self.status
+    }
+
+    /// Sets the status of the running command, e.g. 2 for bad usage. A callback
+    /// returning an error sets it to 1 unless it set one already.
+    pub fn set_status(&mut self, status: i32) {
+        self.status = status;
+    }
+
+    /// Status of the last command that failed, 0 if none did. Batch and
+    /// one-shot runs exit with it.
+    pub fn failure(&self) -> i32 {
+        self.failure
+    }
+
+    /// Ends the shell with `code` once the running command returns.
+    pub fn exit(&mut self, code: i32) {
+        self.exit = Some(code);
+    }
+
+    /// Free form mode shown by the prompt's `%m`, e.g. `config` or `enable`.
+    pub fn mode(&self) -> &str {
+        &self.mode
+    }
+
+    pub fn set_mode(&mut self, mode: &str) {
+        self.mode = mode.to_owned();
+    }
+
+    /// Colors of the shell, `None` when they're off. For custom prompts and
+    /// help renderers.
+    pub fn theme(&self) -> Option<&Theme> {
+        self.theme.as_ref()
+    }
+
+    /// `text` in the theme's colors for `style`, as is when colors are off.
+    pub fn paint(&self, style: Style, text: &str) -> String {
+        theme::paint(self.theme(), style, text)
+    }
+
+    /// Lines per page, `None` uses the terminal height.
+    pub fn page_length(&self) -> Option<usize> {
+        self.page_length
+    }
+
+    /// Sets the lines per page, `Some(0)` turns paging off. Set from the shell
+    /// with `terminal length <lines>`.
+    pub fn set_page_length(&mut self, length: Option<usize>) {
+        self.page_length = length;
+    }
+
+    /// Format for `output`: the `output=` word ending the current line, else
+    /// the `output` shell variable, else the command's default, else the tree's.
+    pub fn format(&self) -> Format {
+        let names = [self.line.get("tx_output_format"), self.vars.get("output"), self.line.get("tx_command_format")];
+        names.iter()
+            .flatten()
+            .filter_map(|name| Format::from_name(name))
+            .next()
+            .unwrap_or(self.format)
+    }
+
+    /// Writes `value` to the command output rendered in the current `format`,
+    /// so the same command serves people and scripts.
+    pub fn output<S: Serialize + ?Sized>(&self, value: &S) -> CommandResult<()> {
+        if self.format() == Format::Table {
+            if let Some(table) = format::to_table(value)? {
+                self.table(table);
+                return Ok(());
+            }
+        }
+        output::write(&format::render(value, self.format())?);
+        Ok(())
+    }
+
+    /// Writes `table` to the command output, keeping only the rows that match
+    /// the line's `| pattern` modifier, if any.
+    pub fn table(&self, mut table: Table) {
+        if let Some(pattern) = self.modifier() {
+            table.filter(pattern);
+        }
+        output::write(&table.render());
+    }
+
+    pub fn cancel_token(&self) -> CancelToken {
+        signal::cancel_token()
+    }
+}
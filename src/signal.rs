@@ -0,0 +1,119 @@
+use std::mem;
+use std::ptr;
+use std::sync::atomic::{AtomicBool, AtomicPtr, AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+use libc;
+use commands::CommandResult;
+
+static CANCELLED: AtomicBool = AtomicBool::new(false);
+/// Set by Ctrl-C, unlike `CANCELLED` which the pager sets too.
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+/// Number of live `SigintGuard`s.
+static GUARDS: AtomicUsize = AtomicUsize::new(0);
+/// SIGINT action from before the outermost guard, for a second Ctrl-C.
+static PREVIOUS: AtomicPtr<libc::sigaction> = AtomicPtr::new(ptr::null_mut());
+
+/// Tells a running callback that the user pressed Ctrl-C. Long running commands
+/// should poll it and return early once it's set.
+#[derive(Debug, Clone, Copy)]
+pub struct CancelToken;
+
+impl CancelToken {
+    pub fn is_cancelled(&self) -> bool {
+        CANCELLED.load(Ordering::SeqCst)
+    }
+
+    /// Shortcut for callbacks: `token.check()?;`
+    pub fn check(&self) -> CommandResult<()> {
+        if self.is_cancelled() {
+            Err("Interrupted".to_owned())
+        }
+        else {
+            Ok(())
+        }
+    }
+
+    /// Sleeps for `duration` unless cancelled first. Returns false if cancelled.
+    pub fn sleep(&self, duration: Duration) -> bool {
+        let deadline = Instant::now() + duration;
+        while !self.is_cancelled() {
+            let now = Instant::now();
+            if now >= deadline {
+                return true;
+            }
+            thread::sleep((deadline - now).min(Duration::from_millis(50)));
+        }
+        false
+    }
+}
+
+/// Token for the command that's currently running.
+pub fn cancel_token() -> CancelToken {
+    CancelToken
+}
+
+/// Cancels the running command as if Ctrl-C was pressed, e.g. on `q` in the pager.
+pub(crate) fn cancel() {
+    CANCELLED.store(true, Ordering::SeqCst);
+}
+
+extern "C" fn on_sigint(_: libc::c_int) {
+    CANCELLED.store(true, Ordering::SeqCst);
+    if !INTERRUPTED.swap(true, Ordering::SeqCst) {
+        return;
+    }
+    // Ctrl-C again before the command stopped: do what it would have done
+    // without the shell, usually killing it
+    unsafe {
+        let previous = PREVIOUS.load(Ordering::SeqCst);
+        if previous.is_null() {
+            libc::signal(libc::SIGINT, libc::SIG_DFL);
+        }
+        else {
+            libc::sigaction(libc::SIGINT, previous, ptr::null_mut());
+        }
+        libc::raise(libc::SIGINT);
+    }
+}
+
+/// Catches SIGINT while alive so Ctrl-C cancels the running command instead of
+/// killing the shell, a second Ctrl-C is handled as before. The previous handler
+/// is restored on drop. Guards nest, the outermost one resets the cancellation.
+pub struct SigintGuard {
+    previous: Box<libc::sigaction>
+}
+
+impl SigintGuard {
+    pub fn install() -> SigintGuard {
+        let outermost = GUARDS.fetch_add(1, Ordering::SeqCst) == 0;
+        if outermost {
+            CANCELLED.store(false, Ordering::SeqCst);
+            INTERRUPTED.store(false, Ordering::SeqCst);
+        }
+        unsafe {
+            let mut action: libc::sigaction = mem::zeroed();
+            action.sa_sigaction = on_sigint as *const () as libc::sighandler_t;
+            libc::sigemptyset(&mut action.sa_mask);
+            let mut previous: Box<libc::sigaction> = Box::new(mem::zeroed());
+            if libc::sigaction(libc::SIGINT, &action, &mut *previous) != 0 {
+                warn!("Could not install SIGINT handler");
+            }
+            if outermost {
+                PREVIOUS.store(&mut *previous, Ordering::SeqCst);
+            }
+            SigintGuard { previous }
+        }
+    }
+}
+
+impl Drop for SigintGuard {
+    fn drop(&mut self) {
+        if GUARDS.fetch_sub(1, Ordering::SeqCst) == 1 {
+            PREVIOUS.store(ptr::null_mut(), Ordering::SeqCst);
+        }
+        unsafe {
+            libc::sigaction(libc::SIGINT, &*self.previous, ptr::null_mut());
+        }
+    }
+}
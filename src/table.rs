@@ -0,0 +1,187 @@
+use std::fmt;
+use std::mem;
+use output;
+
+/// Alignment of the cells in a column.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Align {
+    #[default]
+    Left,
+    Right,
+    Center
+}
+
+/// Rows under a header, aligned in columns, e.g. for `show` commands. Columns
+/// that don't fit in the width are truncated, or wrapped with `set_wrap(true)`.
+/// Print it with `Session::table` so `cmd | pattern` filters its rows.
+#[derive(Debug, Clone, Default)]
+pub struct Table {
+    headers: Vec<String>,
+    align: Vec<Align>,
+    rows: Vec<Vec<String>>,
+    width: Option<usize>,
+    wrap: bool
+}
+
+impl Table {
+    pub fn new<S: AsRef<str>>(headers: &[S]) -> Table {
+        Table {
+            headers: headers.iter().map(|header| header.as_ref().to_owned()).collect(),
+            align: vec![Align::Left; headers.len()],
+            ..Table::default()
+        }
+    }
+
+    pub fn set_align(&mut self, column: usize, align: Align) {
+        if let Some(current) = self.align.get_mut(column) {
+            *current = align;
+        }
+    }
+
+    /// Adds a row, missing cells are left empty and extra ones dropped.
+    pub fn add_row<D: fmt::Display>(&mut self, cells: &[D]) {
+        let mut row: Vec<String> = cells.iter().take(self.headers.len()).map(|cell| cell.to_string()).collect();
+        row.resize(self.headers.len(), String::new());
+        self.rows.push(row);
+    }
+
+    /// Total width to fit in, by default the terminal's when output is one.
+    pub fn set_width(&mut self, width: usize) {
+        self.width = Some(width);
+    }
+
+    /// Wrap cells that don't fit instead of truncating them.
+    pub fn set_wrap(&mut self, wrap: bool) {
+        self.wrap = wrap;
+    }
+
+    pub fn headers(&self) -> &[String] {
+        &self.headers
+    }
+
+    pub fn rows(&self) -> &[Vec<String>] {
+        &self.rows
+    }
+
+    /// Keeps the rows with a cell containing `pattern`, or without one if
+    /// `pattern` starts with `!`. Used for `cmd | pattern`.
+    pub fn filter(&mut self, pattern: &str) {
+        let (keep, pattern) = match pattern.strip_prefix('!') {
+            Some(pattern) => (false, pattern),
+            None => (true, pattern)
+        };
+        self.rows.retain(|row| row.iter().any(|cell| cell.contains(pattern)) == keep);
+    }
+
+    pub fn render(&self) -> String {
+        let widths = self.widths();
+        let mut out = self.render_row(&self.headers, &widths);
+        let dashes: Vec<String> = widths.iter().map(|width| "-".repeat(*width)).collect();
+        out.push_str(&self.render_row(&dashes, &widths));
+        for row in &self.rows {
+            out.push_str(&self.render_row(row, &widths));
+        }
+        out
+    }
+
+    /// Column widths, narrowed from the widest column down until the table
+    /// fits in its width.
+    fn widths(&self) -> Vec<usize> {
+        let mut widths: Vec<usize> = self.headers.iter().map(|header| len(header)).collect();
+        for row in &self.rows {
+            for (width, cell) in widths.iter_mut().zip(row) {
+                *width = (*width).max(len(cell));
+            }
+        }
+        if let Some(max) = self.width.or_else(|| output::terminal_size().map(|(columns, _)| columns)) {
+            let gaps = 2 * widths.len().saturating_sub(1);
+            while widths.iter().sum::<usize>() + gaps > max {
+                let widest = match widths.iter().enumerate().max_by_key(|&(_, width)| *width) {
+                    Some((i, width)) if *width > MIN_WIDTH => i,
+                    _ => break
+                };
+                widths[widest] -= 1;
+            }
+        }
+        widths
+    }
+
+    fn render_row(&self, cells: &[String], widths: &[usize]) -> String {
+        let lines: Vec<Vec<String>> = cells.iter().zip(widths)
+            .map(|(cell, width)| {
+                if self.wrap {
+                    wrap(cell, *width)
+                }
+                else {
+                    vec![truncate(cell, *width)]
+                }
+            })
+            .collect();
+        let height = lines.iter().map(|cell| cell.len()).max().unwrap_or(1);
+        let mut out = String::new();
+        for i in 0..height {
+            let line: Vec<String> = lines.iter().zip(widths).zip(&self.align)
+                .map(|((cell, width), align)| pad(cell.get(i).map(|s| s.as_str()).unwrap_or(""), *width, *align))
+                .collect();
+            out.push_str(line.join("  ").trim_end());
+            out.push('\n');
+        }
+        out
+    }
+}
+
+impl fmt::Display for Table {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.render())
+    }
+}
+
+/// Columns aren't narrowed below this to fit the terminal.
+const MIN_WIDTH: usize = 4;
+
+fn len(text: &str) -> usize {
+    text.chars().count()
+}
+
+fn pad(text: &str, width: usize, align: Align) -> String {
+    let space = width.saturating_sub(len(text));
+    match align {
+        Align::Left => format!("{}{}", text, " ".repeat(space)),
+        Align::Right => format!("{}{}", " ".repeat(space), text),
+        Align::Center => format!("{}{}{}", " ".repeat(space / 2), text, " ".repeat(space - space / 2))
+    }
+}
+
+fn truncate(text: &str, width: usize) -> String {
+    if len(text) <= width {
+        return text.to_owned();
+    }
+    let mut truncated: String = text.chars().take(width.saturating_sub(1)).collect();
+    truncated.push('…');
+    truncated
+}
+
+/// Splits `text` into lines of at most `width` chars, at spaces where possible.
+fn wrap(text: &str, width: usize) -> Vec<String> {
+    let width = width.max(1);
+    let mut lines = Vec::new();
+    let mut line = String::new();
+    for word in text.split(' ') {
+        if !line.is_empty() && len(&line) + 1 + len(word) > width {
+            lines.push(mem::take(&mut line));
+        }
+        let mut chars: Vec<char> = word.chars().collect();
+        if line.is_empty() {
+            while chars.len() > width {
+                lines.push(chars.drain(..width).collect());
+            }
+            line = chars.into_iter().collect();
+        }
+        else {
+            line.push(' ');
+            line.push_str(word);
+        }
+    }
+    lines.push(line);
+    lines
+}
@@ -0,0 +1,188 @@
+use std::collections::VecDeque;
+use std::fmt::Debug;
+use std::fs;
+use std::path::Path;
+use commands::{CommandTree, RunOutcome};
+use output;
+use session::Session;
+
+/// What a line printed and the status it left.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Output {
+    /// Command output, what `shell_println!` and `Session::output` wrote.
+    pub output: String,
+    /// Errors and warnings, e.g. `Error: 'foo' not found`.
+    pub errors: String,
+    /// Output and errors in the order they were printed.
+    pub transcript: String,
+    pub status: i32
+}
+
+impl Output {
+    pub fn success(&self) -> bool {
+        self.status == 0
+    }
+}
+
+/// A shell driven from tests, with scripted lines and golden transcripts.
+/// Lines run as they would in `run`, but nothing is printed, colors and the
+/// pager are off, tables aren't fitted to a width and confirmation prompts
+/// take the answers given with `answer`.
+pub struct TestShell<T>
+    where T: Debug
+{
+    tree: CommandTree<T>,
+    session: Session
+}
+
+impl <T>TestShell<T>
+    where T: Debug
+{
+    /// Runs the start hooks of `tree`, the exit hooks run with `finish`.
+    pub fn new(tree: CommandTree<T>) -> TestShell<T> {
+        let mut session = tree.new_session();
+        session.theme = None;
+        session.answers = Some(VecDeque::new());
+        tree.start();
+        TestShell {
+            tree,
+            session
+        }
+    }
+
+    /// Answers the next confirmation prompt. Without one, commands that
+    /// ask fail as they do when input isn't a terminal.
+    pub fn answer(&mut self, yes: bool) -> &mut TestShell<T> {
+        if let Some(ref mut answers) = self.session.answers {
+            answers.push_back(yes);
+        }
+        self
+    }
+
+    pub fn run(&mut self, line: &str) -> Output {
+        debug!("Test line: {}", line);
+        output::begin_recording();
+        self.tree.execute(line, &mut self.session);
+        let recording = output::end_recording();
+        Output {
+            output: recording.output,
+            errors: recording.errors,
+            transcript: recording.transcript,
+            status: self.session.status()
+        }
+    }
+
+    /// Runs every line of `script` but empty ones and `#` comments. Output is
+    /// joined, the status is the last line's.
+    pub fn run_script(&mut self, script: &str) -> Output {
+        let mut all = Output::default();
+        for line in script.lines().map(|line| line.trim()).filter(|line| !line.is_empty() && !line.starts_with('#')) {
+            let out = self.run(line);
+            all.output.push_str(&out.output);
+            all.errors.push_str(&out.errors);
+            all.transcript.push_str(&out.transcript);
+            all.status = out.status;
+        }
+        all
+    }
+
+    /// The prompt `run` would show now, without colors.
+    pub fn prompt(&self) -> String {
+        self.tree.gen_prompt(&self.session)
+    }
+
+    /// Labels of the entered contexts, outermost first.
+    pub fn context(&self) -> Vec<&str> {
+        self.session.labels()
+    }
+
+    pub fn session(&self) -> &Session {
+        &self.session
+    }
+
+    pub fn session_mut(&mut self) -> &mut Session {
+        &mut self.session
+    }
+
+    /// Calls `f` with the tree's context value.
+    pub fn with_context<F, R>(&self, f: F) -> R
+        where F: FnOnce(&mut Option<T>) -> R
+    {
+        let mut context = self.tree.context.lock().unwrap_or_else(|e| e.into_inner());
+        f(&mut context)
+    }
+
+    /// The code `exit [code]` or `Session::exit` left, if the shell was exited.
+    pub fn exited(&self) -> Option<i32> {
+        self.session.exit
+    }
+
+    /// Runs the exit hooks and returns the code `run` would have.
+    pub fn finish(self) -> RunOutcome {
+        self.tree.exit();
+        RunOutcome { code: self.session.exit.unwrap_or(self.session.failure) }
+    }
+
+    pub fn assert_prompt(&self, expected: &str) {
+        assert_eq!(self.prompt(), expected, "prompt");
+    }
+
+    pub fn assert_context(&self, expected: &[&str]) {
+        assert_eq!(self.context(), expected, "context");
+    }
+
+    /// Runs the `> input` lines of a transcript and checks each one printed
+    /// the lines below it, output and errors alike. Lines before the first
+    /// input are ignored, trailing spaces and empty lines aren't compared.
+    pub fn transcript(&mut self, transcript: &str) -> Result<(), String> {
+        for (number, input, expected) in parse(transcript) {
+            let actual = self.run(input).transcript;
+            if normalize(&actual) != normalize(&expected) {
+                return Err(format!("line {}: > {}\n--- expected\n{}\n--- got\n{}", number, input, normalize(&expected), normalize(&actual)));
+            }
+        }
+        Ok(())
+    }
+
+    /// Same as `transcript`, but panics on the first mismatch.
+    pub fn assert_transcript(&mut self, transcript: &str) {
+        if let Err(e) = self.transcript(transcript) {
+            panic!("transcript mismatch at {}", e);
+        }
+    }
+
+    /// Checks a golden transcript file, e.g. `tests/transcripts/hello.txt`.
+    pub fn assert_transcript_file<P: AsRef<Path>>(&mut self, path: P) {
+        let path = path.as_ref();
+        let transcript = match fs::read_to_string(path) {
+            Ok(transcript) => transcript,
+            Err(e) => panic!("could not read {}: {}", path.display(), e)
+        };
+        if let Err(e) = self.transcript(&transcript) {
+            panic!("transcript mismatch in {} at {}", path.display(), e);
+        }
+    }
+}
+
+/// Inputs of a transcript with their line number and expected output.
+fn parse(transcript: &str) -> Vec<(usize, &str, String)> {
+    let mut steps: Vec<(usize, &str, String)> = Vec::new();
+    for (i, line) in transcript.lines().enumerate() {
+        let input = if line == ">" { Some("") } else { line.strip_prefix("> ") };
+        match input {
+            Some(input) => steps.push((i + 1, input.trim(), String::new())),
+            None => {
+                if let Some(&mut (_, _, ref mut expected)) = steps.last_mut() {
+                    expected.push_str(line);
+                    expected.push('\n');
+                }
+            }
+        }
+    }
+    steps
+}
+
+fn normalize(text: &str) -> String {
+    let lines: Vec<&str> = text.lines().map(|line| line.trim_end()).collect();
+    lines.join("\n").trim_end_matches('\n').to_owned()
+}
@@ -0,0 +1,81 @@
+use std::env;
+use libc;
+
+/// What a piece of text is, so the theme can pick its color.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Style {
+    Prompt,
+    Command,
+    Arg,
+    Error,
+    Warning
+}
+
+/// ANSI SGR codes per style, e.g. `"1;32"` for bold green. An empty code
+/// leaves the text as is.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Theme {
+    pub prompt: String,
+    pub command: String,
+    pub arg: String,
+    pub error: String,
+    pub warning: String
+}
+
+impl Theme {
+    pub fn new() -> Theme {
+        Theme {
+            prompt: "1;32".to_owned(),
+            command: "1".to_owned(),
+            arg: "36".to_owned(),
+            error: "1;31".to_owned(),
+            warning: "33".to_owned()
+        }
+    }
+
+    /// No colors at all.
+    pub fn plain() -> Theme {
+        Theme {
+            prompt: String::new(),
+            command: String::new(),
+            arg: String::new(),
+            error: String::new(),
+            warning: String::new()
+        }
+    }
+
+    pub fn code(&self, style: Style) -> &str {
+        match style {
+            Style::Prompt => &self.prompt,
+            Style::Command => &self.command,
+            Style::Arg => &self.arg,
+            Style::Error => &self.error,
+            Style::Warning => &self.warning
+        }
+    }
+}
+
+impl Default for Theme {
+    fn default() -> Theme {
+        Theme::new()
+    }
+}
+
+/// Colors are on unless `NO_COLOR` is set or stdout isn't a terminal, so
+/// redirected output stays free of escape codes. `CommandTree::set_color`
+/// (`--no-color` does that) overrides it.
+pub fn color_enabled() -> bool {
+    if env::var_os("NO_COLOR").is_some() {
+        return false;
+    }
+    unsafe { libc::isatty(libc::STDOUT_FILENO) == 1 }
+}
+
+/// Wraps `text` in the escape codes of `theme` for `style`, leaves it as is
+/// without a theme, i.e. when colors are off.
+pub fn paint(theme: Option<&Theme>, style: Style, text: &str) -> String {
+    match theme.map(|theme| theme.code(style)) {
+        Some(code) if !code.is_empty() => format!("\x1b[{}m{}\x1b[0m", code, text),
+        _ => text.to_owned()
+    }
+}
@@ -0,0 +1,135 @@
+use std::collections::HashMap;
+use std::env;
+
+use line::Token;
+
+pub type Variables = HashMap<String, String>;
+
+pub fn is_valid_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => (),
+        _ => return false
+    }
+    chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Looks `name` up in the shell variables first, then in the process environment.
+pub fn lookup(vars: &Variables, name: &str) -> Option<String> {
+    vars.get(name).cloned().or_else(|| env::var(name).ok())
+}
+
+/// Replaces `$name` and `${name}` with their values and `$(cmd)` with whatever
+/// `capture` returns for `cmd`. `$?` is the variable named `?`, the status of
+/// the last command. A `$` that doesn't start a variable name is kept as is,
+/// `\$` produces a literal `$`.
+pub fn expand<F>(line: &str, vars: &Variables, mut capture: F) -> Result<String, String>
+    where F: FnMut(&str) -> Result<String, String>
+{
+    let mut result = String::with_capacity(line.len());
+    let mut chars = line.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if chars.peek() == Some(&'$') => {
+                chars.next();
+                result.push('$');
+            },
+            '$' if chars.peek() == Some(&'(') => {
+                chars.next();
+                let mut cmd = String::new();
+                let mut depth = 0;
+                loop {
+                    match chars.next() {
+                        Some(')') if depth == 0 => break,
+                        Some(c) => {
+                            match c {
+                                '(' => depth += 1,
+                                ')' => depth -= 1,
+                                _ => ()
+                            }
+                            cmd.push(c);
+                        },
+                        None => return Err(format!("missing ')' after '$({}'", cmd))
+                    }
+                }
+                result.push_str(&capture(cmd.trim())?);
+            },
+            '$' => {
+                let mut name = String::new();
+                if chars.peek() == Some(&'?') {
+                    chars.next();
+                    name.push('?');
+                }
+                else if chars.peek() == Some(&'{') {
+                    chars.next();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => name.push(c),
+                            None => return Err(format!("missing '}}' after '${{{}'", name))
+                        }
+                    }
+                    if !is_valid_name(&name) && name != "?" {
+                        return Err(format!("bad variable name '{}'", name));
+                    }
+                }
+                else {
+                    while let Some(&c) = chars.peek() {
+                        if c.is_ascii_alphanumeric() || c == '_' {
+                            name.push(c);
+                            chars.next();
+                        }
+                        else {
+                            break;
+                        }
+                    }
+                    if !is_valid_name(&name) {
+                        result.push('$');
+                        result.push_str(&name);
+                        continue;
+                    }
+                }
+                match lookup(vars, &name) {
+                    Some(val) => result.push_str(&val),
+                    None => return Err(format!("variable '{}' is not set", name))
+                }
+            },
+            _ => result.push(c)
+        }
+    }
+    Ok(result)
+}
+
+/// Parses the arguments of `set`: `name value`, `name=value` or `name = value`.
+/// The value is the rest of the words as they were split, so quoted spaces and
+/// quotes are kept.
+pub fn parse_assignment(args: &[Token]) -> Result<(String, String), String> {
+    let (first, rest) = match args.split_first() {
+        Some(split) => split,
+        None => return Err("set needs a variable name".to_owned())
+    };
+    let mut value: Vec<&str> = rest.iter().map(|word| word.text.as_str()).collect();
+    let name = match first.text.find('=') {
+        Some(pos) if !first.quoted => {
+            if pos + 1 < first.text.len() {
+                value.insert(0, &first.text[pos + 1..]);
+            }
+            &first.text[..pos]
+        },
+        _ => {
+            match rest.first() {
+                Some(word) if !word.quoted && word.text == "=" => {
+                    value.remove(0);
+                },
+                Some(word) if !word.quoted && word.text.starts_with('=') => value[0] = &word.text[1..],
+                _ => ()
+            }
+            first.text.as_str()
+        }
+    };
+    if !is_valid_name(name) {
+        return Err(format!("bad variable name '{}'", name));
+    }
+    Ok((name.to_owned(), value.join(" ")))
+}
@@ -0,0 +1,68 @@
+#[macro_use]
+extern crate tshell;
+
+use std::collections::HashMap;
+use tshell::{CommandResult, CommandTree, Session, Shell};
+use tshell::output;
+
+fn greet(args: HashMap<String, &str>, _context: &mut Option<()>, _session: &mut Session) -> CommandResult<Option<String>> {
+    shell_println!("Hello {}", args["name"]);
+    Ok(None)
+}
+
+fn reset(_args: HashMap<String, &str>, _context: &mut Option<()>, _session: &mut Session) -> CommandResult<Option<String>> {
+    shell_println!("Counters reset");
+    Ok(None)
+}
+
+fn tree() -> CommandTree<()> {
+    shell_command_tree!{cli,
+        "Cli",
+        "1.0",
+        (),
+        [
+            shell_command_node!{
+                cmd: greet,
+                txt_help: "Greet someone",
+                callback: greet,
+                args: [name => true]
+            },
+            shell_command_node!{
+                cmd: reset,
+                txt_help: "Reset counters",
+                confirm: "Reset all counters?",
+                callback: reset
+            }
+        ]
+    }
+}
+
+fn run(args: &[&str]) -> (String, i32) {
+    let mut tree = tree();
+    output::begin_capture();
+    let outcome = tree.run_from_args(args.iter().map(|arg| (*arg).to_owned()));
+    (output::end_capture(), outcome.code)
+}
+
+#[test]
+fn words_are_taken_as_they_are() {
+    assert_eq!(run(&["cli", "greet", "$HOME"]), ("Hello $HOME\n".to_owned(), 0));
+    assert_eq!(run(&["cli", "greet", "\"bob"]), ("Hello \"bob\n".to_owned(), 0));
+    assert_eq!(run(&["cli", "greet", "bob > out"]), ("Hello bob > out\n".to_owned(), 0));
+}
+
+#[test]
+fn options_before_the_command() {
+    assert_eq!(run(&["cli", "--no-color", "--yes", "reset"]), ("Counters reset\n".to_owned(), 0));
+    assert_eq!(run(&["cli", "greet", "--yes"]), ("Hello --yes\n".to_owned(), 0));
+}
+
+#[test]
+fn complete_for_the_completion_scripts() {
+    assert_eq!(run(&["cli", "__complete", "gr"]), ("greet\n".to_owned(), 0));
+    assert_eq!(run(&["cli", "__complete", "re"]), ("reset\n".to_owned(), 0));
+    assert_eq!(run(&["cli", "__complete", "bogus", ""]), ("".to_owned(), 0));
+    for shell in &[Shell::Bash, Shell::Zsh, Shell::Fish] {
+        assert!(tree().completion_script(*shell).contains("cli __complete "));
+    }
+}
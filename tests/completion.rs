@@ -0,0 +1,127 @@
+#[macro_use]
+extern crate tshell;
+
+use std::collections::HashMap;
+use tshell::{CommandResult, CommandTree, Completion, Session};
+
+fn noop(_args: HashMap<String, &str>, _context: &mut Option<()>, _session: &mut Session) -> CommandResult<Option<String>> {
+    Ok(None)
+}
+
+fn tree() -> CommandTree<()> {
+    shell_command_tree!{router,
+        "Router",
+        "1.0",
+        (),
+        [
+            shell_command_node!{
+                cmd: show,
+                txt_help: "Show state",
+                nodes: [
+                    shell_command_node!{
+                        cmd: version,
+                        txt_help: "Version",
+                        callback: noop
+                    },
+                    shell_command_node!{
+                        cmd: vlans,
+                        txt_help: "VLANs",
+                        callback: noop
+                    }
+                ]
+            },
+            shell_command_node!{
+                cmd: interface,
+                txt_help: "Configure an interface",
+                callback: noop,
+                args: [name => true],
+                choices: [name => ["eth0", "eth1", "port channel 1", "lab \"a\""]],
+                nodes: [
+                    shell_command_node!{
+                        cmd: mtu,
+                        txt_help: "Set the MTU",
+                        callback: noop,
+                        args: [size => true]
+                    }
+                ]
+            },
+            shell_command_node!{
+                cmd: ping,
+                txt_help: "Ping a host",
+                callback: noop,
+                args: [host => true, count => false, size => false],
+                choices: [count => ["1", "5", "10"]]
+            }
+        ]
+    }
+}
+
+fn completion(start: usize, end: usize, candidates: &[&str]) -> Completion {
+    Completion {
+        start,
+        end,
+        candidates: candidates.iter().map(|candidate| (*candidate).to_owned()).collect()
+    }
+}
+
+#[test]
+fn commands_at_the_end_of_the_line() {
+    let tree = tree();
+    assert_eq!(tree.complete("", 0), completion(0, 0, &["show", "interface", "ping", "exit", "quit"]));
+    assert_eq!(tree.complete("sh", 2), completion(0, 2, &["show"]));
+    assert_eq!(tree.complete("show ", 5), completion(5, 5, &["version", "vlans"]));
+    assert_eq!(tree.complete("show v", 6), completion(5, 6, &["version", "vlans"]));
+    assert_eq!(tree.complete("show  ve", 8), completion(6, 8, &["version"]));
+}
+
+#[test]
+fn cursor_in_the_middle_of_the_line() {
+    let tree = tree();
+    // Only what's before the cursor counts, the whole word is replaced
+    assert_eq!(tree.complete("sh version", 2), completion(0, 2, &["show"]));
+    assert_eq!(tree.complete("show ver", 7), completion(5, 8, &["version"]));
+    assert_eq!(tree.complete("shxx vlans", 2), completion(0, 4, &["show"]));
+    // Between words a new one is inserted
+    assert_eq!(tree.complete("show  vlans", 5), completion(5, 5, &["version", "vlans"]));
+}
+
+#[test]
+fn arg_choices_and_quoting() {
+    let tree = tree();
+    assert_eq!(tree.complete("interface e", 11), completion(10, 11, &["eth0", "eth1"]));
+    assert_eq!(tree.complete("interface ", 10), completion(10, 10, &["eth0", "eth1", "\"port channel 1\"", "\"lab \\\"a\\\"\""]));
+    // An open quote is part of the word it replaces
+    assert_eq!(tree.complete("interface \"port ch", 18), completion(10, 18, &["\"port channel 1\""]));
+    assert_eq!(tree.complete("interface \"port channel 1\" m", 28), completion(27, 28, &["mtu"]));
+    // Quotes inside a candidate are escaped
+    assert_eq!(tree.complete("interface l", 11), completion(10, 11, &["\"lab \\\"a\\\"\""]));
+    assert_eq!(tree.complete("interface \"lab \\\"a", 18), completion(10, 18, &["\"lab \\\"a\\\"\""]));
+    assert_eq!(tree.complete("interface \"lab \\\"a\\\"\" m", 23), completion(22, 23, &["mtu"]));
+}
+
+#[test]
+fn optional_args() {
+    let tree = tree();
+    assert_eq!(tree.complete("ping host c", 11), completion(10, 11, &["count="]));
+    assert_eq!(tree.complete("ping host count=", 16), completion(10, 16, &["count=1", "count=5", "count=10"]));
+    assert_eq!(tree.complete("ping host count=1 s", 19), completion(18, 19, &["size="]));
+}
+
+#[test]
+fn contexts() {
+    let tree = tree();
+    assert_eq!(tree.complete_in(&["interface"], "m", 1), completion(0, 1, &["mtu"]));
+    assert_eq!(tree.complete_in(&["interface"], "/show/v", 7), completion(0, 7, &["/show/version", "/show/vlans"]));
+    assert_eq!(tree.complete_in(&["interface"], "/show v", 7), completion(6, 7, &["version", "vlans"]));
+}
+
+#[test]
+fn nothing_to_complete() {
+    let tree = tree();
+    assert_eq!(tree.complete("bogus ", 6), completion(6, 6, &[]));
+    // Missing required args aren't candidates
+    assert_eq!(tree.complete("interface", 9), completion(0, 9, &[]));
+    // Cursors past the end or inside a char are clamped
+    assert_eq!(tree.complete("sh", 10), completion(0, 2, &["show"]));
+    assert_eq!(tree.complete("é", 1), completion(0, 0, &["show", "interface", "ping", "exit", "quit"]));
+}
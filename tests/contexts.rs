@@ -0,0 +1,123 @@
+#[macro_use]
+extern crate tshell;
+
+use std::collections::HashMap;
+use tshell::{CommandResult, CommandTree, Completion, Session};
+use tshell::testing::TestShell;
+
+fn version(_args: HashMap<String, &str>, _context: &mut Option<()>, _session: &mut Session) -> CommandResult<Option<String>> {
+    shell_println!("Router 1.0");
+    Ok(None)
+}
+
+fn ping(args: HashMap<String, &str>, _context: &mut Option<()>, session: &mut Session) -> CommandResult<Option<String>> {
+    shell_println!("{} ping {}", session.labels().join("/"), args["host"]);
+    Ok(None)
+}
+
+fn enter(args: HashMap<String, &str>, _context: &mut Option<()>, session: &mut Session) -> CommandResult<Option<String>> {
+    let name = args.get("name").or_else(|| args.get("id")).cloned().unwrap_or("");
+    Ok(Some(format!("{}:{}", session.command_path().join(" "), name)))
+}
+
+fn tree() -> CommandTree<()> {
+    shell_command_tree!{router,
+        "Router",
+        "1.0",
+        (),
+        [
+            shell_command_node!{
+                cmd: show,
+                txt_help: "Show state",
+                nodes: [
+                    shell_command_node!{
+                        cmd: version,
+                        txt_help: "Version",
+                        callback: version
+                    }
+                ]
+            },
+            shell_command_node!{
+                cmd: tools,
+                txt_help: "Diagnostics",
+                nodes: [
+                    shell_command_node!{
+                        cmd: ping,
+                        txt_help: "Ping a host",
+                        callback: ping,
+                        global: true,
+                        args: [host => true]
+                    }
+                ]
+            },
+            shell_command_node!{
+                cmd: interface,
+                txt_help: "Configure an interface",
+                callback: enter,
+                context: true,
+                args: [name => true]
+            },
+            shell_command_node!{
+                cmd: vlan,
+                txt_help: "Configure a VLAN",
+                callback: enter,
+                context: true,
+                fallback: true,
+                args: [id => true]
+            }
+        ]
+    }
+}
+
+#[test]
+fn global_commands_run_from_any_context() {
+    let mut shell = TestShell::new(tree());
+    shell.run("interface eth0");
+    assert_eq!(shell.run("ping 10.0.0.1").output, "eth0 ping 10.0.0.1\n");
+    shell.assert_context(&["eth0"]);
+    // Other commands of the top level aren't reachable without fallback
+    assert_eq!(shell.run("show version").status, 127);
+}
+
+#[test]
+fn fallback_contexts_reach_their_ancestors() {
+    let mut shell = TestShell::new(tree());
+    shell.run("vlan 10");
+    assert_eq!(shell.run("show version").output, "Router 1.0\n");
+    shell.assert_context(&["10"]);
+
+    // An inherited context replaces the current one
+    shell.run("interface eth0");
+    shell.assert_context(&["eth0"]);
+}
+
+#[test]
+fn help_lists_global_and_inherited_commands() {
+    let mut shell = TestShell::new(tree());
+    shell.run("vlan 10");
+    let out = shell.run("help").output;
+    assert!(out.contains("Global commands:\n  ping:\tPing a host"));
+    assert!(out.contains("Inherited commands:\n  show:\tShow state\n"));
+
+    shell.run("/interface eth0");
+    let out = shell.run("help").output;
+    assert!(out.contains("Global commands:\n  ping:\tPing a host"));
+    assert!(!out.contains("Inherited commands:"));
+}
+
+fn completion(start: usize, end: usize, candidates: &[&str]) -> Completion {
+    Completion {
+        start,
+        end,
+        candidates: candidates.iter().map(|candidate| (*candidate).to_owned()).collect()
+    }
+}
+
+#[test]
+fn global_and_inherited_commands_complete() {
+    let tree = tree();
+    assert_eq!(tree.complete_in(&["interface"], "pi", 2), completion(0, 2, &["ping"]));
+    assert_eq!(tree.complete_in(&["interface"], "sh", 2), completion(0, 2, &[]));
+    assert_eq!(tree.complete_in(&["vlan"], "sh", 2), completion(0, 2, &["show"]));
+    assert_eq!(tree.complete_in(&["vlan"], "show v", 6), completion(5, 6, &["version"]));
+}
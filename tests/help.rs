@@ -0,0 +1,65 @@
+#[macro_use]
+extern crate tshell;
+
+use std::collections::HashMap;
+use tshell::{CommandResult, CommandTree, HelpRenderer, ManHelp, MarkdownHelp, Session};
+
+fn noop(_args: HashMap<String, &str>, _context: &mut Option<()>, _session: &mut Session) -> CommandResult<Option<String>> {
+    Ok(None)
+}
+
+fn tree() -> CommandTree<()> {
+    shell_command_tree!{router,
+        "Router",
+        "1.0",
+        (),
+        [
+            shell_command_node!{
+                cmd: interface,
+                txt_help: "Configure an interface",
+                long_help: "Enters the interface.\n.ports are named eth0, eth1 and so on",
+                callback: noop,
+                context: true,
+                args: [name => true],
+                arg_help: [name => "Port name"],
+                examples: ["interface eth0"],
+                nodes: [
+                    shell_command_node!{
+                        cmd: mtu,
+                        txt_help: "Set the MTU",
+                        callback: noop,
+                        args: [size => false],
+                        defaults: [size => "1500"],
+                        arg_help: [size => "Bytes per frame"]
+                    }
+                ]
+            },
+            shell_command_node!{
+                cmd: traceroute,
+                txt_help: "Trace the route to a host, -n for no lookups",
+                callback: noop
+            }
+        ]
+    }
+}
+
+#[test]
+fn man_page_escapes_roff() {
+    let man = ManHelp::new("1.0").render(&tree().help_entry());
+    assert!(man.starts_with(".TH \"ROUTER\" 1 \"\" \"router 1.0\"\n.SH NAME\nrouter \\- Router\n"));
+    assert!(man.contains(".TP\n\\fBtraceroute\\fR\nTrace the route to a host, \\-n for no lookups\n"));
+    // A description line starting with a dot isn't a request
+    assert!(man.contains(".PP\nEnters the interface.\n\\&.ports are named eth0, eth1 and so on\n"));
+    assert!(man.contains(".TP\n\\fBinterface mtu\\fR [\\fIsize\\fR=1500]\n"));
+}
+
+#[test]
+fn markdown_has_a_section_per_command() {
+    let markdown = MarkdownHelp.render(&tree().help_entry());
+    assert!(markdown.starts_with("## router\n\nRouter\n\n### `interface <name>`\n\nConfigure an interface\n"));
+    assert!(markdown.contains("\nEnters a context, the commands below run inside it.\n"));
+    assert!(markdown.contains("| `name` | yes |  | Port name |\n"));
+    assert!(markdown.contains("\nExamples:\n\n```\ninterface eth0\n```\n"));
+    assert!(markdown.contains("\n### `interface mtu [size=1500]`\n\nSet the MTU\n"));
+    assert!(markdown.contains("| `size` | no | `1500` | Bytes per frame |\n"));
+}
@@ -0,0 +1,73 @@
+#[macro_use]
+extern crate tshell;
+extern crate serde_json;
+
+use std::collections::{BTreeMap, HashMap};
+use tshell::{CommandResult, CommandTree, Format, HelpRenderer, JsonHelp, Session};
+use tshell::testing::TestShell;
+
+fn mtu(_args: HashMap<String, &str>, _context: &mut Option<()>, _session: &mut Session) -> CommandResult<BTreeMap<&'static str, u32>> {
+    let mut mtu = BTreeMap::new();
+    mtu.insert("eth0", 1500);
+    Ok(mtu)
+}
+
+fn save(args: HashMap<String, &str>, _context: &mut Option<()>, _session: &mut Session) -> CommandResult<Option<String>> {
+    shell_println!("saved as {}", args["output"]);
+    Ok(None)
+}
+
+fn tree() -> CommandTree<()> {
+    shell_command_tree!{router,
+        "Router",
+        "1.0",
+        (),
+        [
+            shell_command_node!{
+                cmd: mtu,
+                txt_help: "MTU per interface",
+                structured: mtu,
+                output: Format::Json
+            },
+            shell_command_node!{
+                cmd: save,
+                txt_help: "Save the configuration",
+                callback: save,
+                args: [output => false],
+                defaults: [output => "text"]
+            }
+        ]
+    }
+}
+
+#[test]
+fn structured_callbacks_use_the_command_format() {
+    let mut shell = TestShell::new(tree());
+    assert_eq!(shell.run("mtu").output, "{\n  \"eth0\": 1500\n}\n");
+    assert_eq!(shell.run("mtu output=yaml").output, "eth0: 1500\n");
+
+    shell.run("set output yaml");
+    assert_eq!(shell.run("mtu").output, "eth0: 1500\n");
+}
+
+#[test]
+fn output_word_only_at_the_end() {
+    let mut shell = TestShell::new(tree());
+    assert!(!shell.run("mtu \"output=yaml\"").success());
+    assert!(!shell.run("mtu output=yaml extra").success());
+}
+
+#[test]
+fn output_arg_of_the_command() {
+    let mut shell = TestShell::new(tree());
+    assert_eq!(shell.run("save").output, "saved as text\n");
+    assert_eq!(shell.run("save output=json").output, "saved as json\n");
+}
+
+#[test]
+fn json_help() {
+    let help: serde_json::Value = serde_json::from_str(&JsonHelp.render(&tree().help_entry())).unwrap();
+    assert_eq!(help["subcommands"][1]["name"], "save");
+    assert_eq!(help["subcommands"][1]["args"][0]["default"], "text");
+    assert_eq!(help["subcommands"][0]["args"], serde_json::json!([]));
+}
@@ -0,0 +1,66 @@
+#[macro_use]
+extern crate tshell;
+
+use std::collections::HashMap;
+use tshell::{CommandResult, CommandTree, PromptFormat, Session};
+use tshell::testing::TestShell;
+
+fn interface(args: HashMap<String, &str>, _context: &mut Option<()>, _session: &mut Session) -> CommandResult<Option<String>> {
+    Ok(Some(format!("interface:{}", args["name"])))
+}
+
+fn vlan(args: HashMap<String, &str>, _context: &mut Option<()>, _session: &mut Session) -> CommandResult<Option<String>> {
+    Ok(Some(format!("vlan:{}", args["id"])))
+}
+
+fn shell(format: &str) -> TestShell<()> {
+    let mut tree: CommandTree<()> = shell_command_tree!{router,
+        "Router",
+        "1.0",
+        (),
+        [
+            shell_command_node!{
+                cmd: interface,
+                txt_help: "Configure an interface",
+                callback: interface,
+                context: true,
+                args: [name => true],
+                nodes: [
+                    shell_command_node!{
+                        cmd: vlan,
+                        txt_help: "Configure a VLAN",
+                        callback: vlan,
+                        context: true,
+                        args: [id => true]
+                    }
+                ]
+            }
+        ]
+    };
+    tree.set_prompt(PromptFormat::new(format));
+    TestShell::new(tree)
+}
+
+#[test]
+fn name_and_contexts() {
+    let mut shell = shell("%n|%c|%p> ");
+    shell.assert_prompt("router||router> ");
+    shell.run("interface eth0");
+    shell.run("vlan 10");
+    shell.assert_prompt("router|eth0/10|router/eth0/10> ");
+}
+
+#[test]
+fn status_and_mode() {
+    let mut shell = shell("[%s] %m> ");
+    shell.assert_prompt("[0] > ");
+    shell.run("bogus");
+    shell.session_mut().set_mode("config");
+    shell.assert_prompt("[127] config> ");
+}
+
+#[test]
+fn percent_signs() {
+    shell("100%% %x> ").assert_prompt("100% %x> ");
+    shell("%n%").assert_prompt("router%");
+}
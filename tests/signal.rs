@@ -0,0 +1,22 @@
+extern crate libc;
+extern crate tshell;
+
+use tshell::cancel_token;
+use tshell::signal::SigintGuard;
+
+#[test]
+fn nested_guards_keep_the_cancellation() {
+    let outer = SigintGuard::install();
+    unsafe {
+        libc::raise(libc::SIGINT);
+    }
+    assert!(cancel_token().is_cancelled());
+
+    // A guard for a command run from the callback doesn't clear it
+    drop(SigintGuard::install());
+    assert!(cancel_token().is_cancelled());
+    drop(outer);
+
+    let _next = SigintGuard::install();
+    assert!(!cancel_token().is_cancelled());
+}
@@ -0,0 +1,64 @@
+extern crate tshell;
+
+use tshell::{Align, Table};
+
+fn interfaces() -> Table {
+    let mut table = Table::new(&["port", "description"]);
+    table.add_row(&["eth0", "uplink"]);
+    table.add_row(&["eth1", "description"]);
+    table
+}
+
+#[test]
+fn columns_fit_the_width() {
+    let mut table = interfaces();
+    table.set_width(40);
+    assert_eq!(table.render(), "port  description\n----  -----------\neth0  uplink\neth1  description\n");
+
+    // The widest column gives way first, cut with an ellipsis
+    table.set_width(14);
+    assert_eq!(table.render(), "port  descrip…\n----  --------\neth0  uplink\neth1  descrip…\n");
+}
+
+#[test]
+fn columns_stop_at_the_minimum_width() {
+    let mut table = interfaces();
+    table.set_width(1);
+    assert_eq!(table.render(), "port  des…\n----  ----\neth0  upl…\neth1  des…\n");
+}
+
+#[test]
+fn wrapped_cells_take_more_lines() {
+    let mut table = interfaces();
+    table.set_width(10);
+    table.set_wrap(true);
+    assert_eq!(table.render(), "port  desc\n      ript\n      ion\n----  ----\neth0  upli\n      nk\neth1  desc\n      ript\n      ion\n");
+
+    let mut table = Table::new(&["port", "description"]);
+    table.add_row(&["eth0", "to core"]);
+    table.set_width(13);
+    table.set_wrap(true);
+    assert!(table.render().ends_with("eth0  to core\n"));
+}
+
+#[test]
+fn cells_are_aligned() {
+    let mut table = Table::new(&["name", "mtu", "state"]);
+    table.add_row(&["eth0", "9000", "up"]);
+    table.add_row(&["eth1", "1500", "down"]);
+    table.set_align(1, Align::Right);
+    table.set_align(2, Align::Center);
+    table.set_width(80);
+    assert_eq!(table.render(), "name   mtu  state\n----  ----  -----\neth0  9000   up\neth1  1500  down\n");
+}
+
+#[test]
+fn filter_keeps_or_drops_matching_rows() {
+    let mut table = interfaces();
+    table.filter("up");
+    assert_eq!(table.rows(), &[vec!["eth0".to_owned(), "uplink".to_owned()]]);
+
+    let mut table = interfaces();
+    table.filter("!up");
+    assert_eq!(table.rows(), &[vec!["eth1".to_owned(), "description".to_owned()]]);
+}
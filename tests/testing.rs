@@ -0,0 +1,391 @@
+#[macro_use]
+extern crate tshell;
+
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use tshell::{CommandResult, CommandTree, Session};
+use tshell::testing::TestShell;
+
+#[derive(Debug, Default)]
+struct Router {
+    resets: u32
+}
+
+fn version(_args: HashMap<String, &str>, _router: &mut Option<Router>, _session: &mut Session) -> CommandResult<Option<String>> {
+    shell_println!("Router 1.0");
+    Ok(None)
+}
+
+fn interface(args: HashMap<String, &str>, _router: &mut Option<Router>, _session: &mut Session) -> CommandResult<Option<String>> {
+    Ok(Some(format!("interface:{}", args["name"])))
+}
+
+fn mtu(args: HashMap<String, &str>, _router: &mut Option<Router>, session: &mut Session) -> CommandResult<Option<String>> {
+    let size: u32 = args["size"].parse().map_err(|_| format!("bad mtu '{}'", args["size"]))?;
+    shell_println!("{} mtu {}", session.labels().join("/"), size);
+    Ok(None)
+}
+
+fn reset(_args: HashMap<String, &str>, router: &mut Option<Router>, _session: &mut Session) -> CommandResult<Option<String>> {
+    if let Some(ref mut router) = *router {
+        router.resets += 1;
+    }
+    shell_println!("Counters reset");
+    Ok(None)
+}
+
+fn tree() -> CommandTree<Router> {
+    shell_command_tree!{router,
+        "Router",
+        "1.0",
+        Router::default(),
+        [
+            shell_command_node!{
+                cmd: show,
+                txt_help: "Show state",
+                nodes: [
+                    shell_command_node!{
+                        cmd: version,
+                        txt_help: "Version",
+                        callback: version
+                    }
+                ]
+            },
+            shell_command_node!{
+                cmd: interface,
+                txt_help: "Configure an interface",
+                callback: interface,
+                context: true,
+                args: [name => true],
+                nodes: [
+                    shell_command_node!{
+                        cmd: mtu,
+                        txt_help: "Set the MTU",
+                        callback: mtu,
+                        args: [size => true]
+                    }
+                ]
+            },
+            shell_command_node!{
+                cmd: reset,
+                txt_help: "Reset counters",
+                confirm: "Reset all counters?",
+                callback: reset
+            }
+        ]
+    }
+}
+
+#[test]
+fn run_captures_output_and_status() {
+    let mut shell = TestShell::new(tree());
+    let out = shell.run("show version");
+    assert_eq!(out.output, "Router 1.0\n");
+    assert_eq!(out.errors, "");
+    assert!(out.success());
+
+    let out = shell.run("show nothing");
+    assert_eq!(out.output, "");
+    assert!(out.errors.starts_with("Error: "));
+    assert_eq!(out.status, 127);
+}
+
+#[test]
+fn callback_errors_fail_the_line() {
+    let mut shell = TestShell::new(tree());
+    let out = shell.run_script("
+        # bad sizes are rejected
+        interface eth0
+        mtu big
+    ");
+    assert_eq!(out.errors, "Error: bad mtu 'big'\n");
+    assert_eq!(out.status, 1);
+    shell.run("set status $?");
+    assert_eq!(shell.session().var("status"), Some("1".to_owned()));
+}
+
+#[test]
+fn contexts_and_prompts() {
+    let mut shell = TestShell::new(tree());
+    shell.assert_prompt("router>> ");
+    shell.assert_context(&[]);
+
+    shell.run("interface eth0");
+    shell.assert_prompt("router/eth0>> ");
+    shell.assert_context(&["eth0"]);
+    assert_eq!(shell.session().path(), vec!["interface"]);
+    assert_eq!(shell.run("mtu 9000").output, "eth0 mtu 9000\n");
+
+    shell.run("up");
+    shell.assert_context(&[]);
+}
+
+#[test]
+fn confirmations_take_scripted_answers() {
+    let mut shell = TestShell::new(tree());
+    let out = shell.run("reset");
+    assert!(!out.success());
+    assert!(out.errors.contains("needs confirmation"));
+
+    shell.answer(false);
+    assert_eq!(shell.run("reset").errors, "Aborted.\n");
+
+    shell.answer(true);
+    assert_eq!(shell.run("reset").output, "Counters reset\n");
+    assert_eq!(shell.with_context(|router| router.as_ref().map(|router| router.resets)), Some(1));
+}
+
+#[test]
+fn exit_code() {
+    let mut shell = TestShell::new(tree());
+    shell.run("show nothing");
+    shell.run("exit 3");
+    assert_eq!(shell.exited(), Some(3));
+    assert_eq!(shell.run("show version").output, "");
+    assert_eq!(shell.finish().code, 3);
+}
+
+#[test]
+fn inline_transcript() {
+    let mut shell = TestShell::new(tree());
+    shell.assert_transcript("
+> show version
+Router 1.0
+> interface eth1
+> mtu 1500
+eth1 mtu 1500
+");
+    assert!(shell.transcript("> show version\nRouter 2.0\n").unwrap_err().starts_with("line 1: > show version"));
+}
+
+#[test]
+fn golden_transcripts() {
+    TestShell::new(tree()).assert_transcript_file("tests/transcripts/router.txt");
+}
+
+#[test]
+fn context_for_one_line() {
+    let mut shell = TestShell::new(tree());
+    let out = shell.run("interface eth0 mtu 9000");
+    assert_eq!(out.output, "eth0 mtu 9000\n");
+    assert!(out.success());
+    shell.assert_prompt("router>> ");
+    shell.assert_context(&[]);
+    assert_eq!(shell.session().depth(), 0);
+
+    // From inside another context the stack is left as it was
+    shell.run("interface eth1");
+    shell.run("/interface eth2 mtu 1500");
+    shell.assert_prompt("router/eth1>> ");
+    shell.assert_context(&["eth1"]);
+}
+
+#[test]
+fn variables_stay_in_their_word() {
+    let mut shell = TestShell::new(tree());
+    shell.run("set name = \"eth0 > saved | more\"");
+    assert!(shell.run("interface $name").success());
+    shell.assert_context(&["eth0 > saved | more"]);
+
+    let out = shell.run("mtu \"$(/show version)\"");
+    assert_eq!(out.errors, "Error: bad mtu 'Router 1.0'\n");
+}
+
+#[test]
+fn failed_capture_fails_the_line() {
+    let mut shell = TestShell::new(tree());
+    let out = shell.run("set id = $(bogus)");
+    assert!(!out.success());
+    assert_eq!(out.errors, "Error: command 'bogus' not found\nError: '$(bogus)' failed\n");
+    assert!(shell.session().var("id").is_none());
+
+    assert!(shell.run("set id = $(show version)").success());
+    assert_eq!(shell.session().var("id"), Some("Router 1.0".to_owned()));
+}
+
+#[test]
+fn yes_only_as_trailing_flag() {
+    let mut shell = TestShell::new(tree());
+    let out = shell.run("reset --yes");
+    assert_eq!(out.output, "Counters reset\n");
+
+    // Quoted or in the middle of the line, it's just a word
+    assert!(!shell.run("reset \"--yes\"").success());
+    assert_eq!(shell.run("interface --yes mtu 1500").output, "--yes mtu 1500\n");
+}
+
+#[test]
+fn help_lists_first_level_commands() {
+    let mut shell = TestShell::new(tree());
+    let out = shell.run("help");
+    assert!(out.output.contains("  show:\tShow state\n"));
+    assert!(out.output.contains("  interface:\tConfigure an interface"));
+    assert!(!out.output.contains("version"));
+    assert!(!out.output.contains("mtu"));
+
+    let all = shell.run("help --all");
+    assert!(all.output.contains("    version:\tVersion\n"));
+    assert!(all.output.contains("    mtu:\tSet the MTU"));
+}
+
+#[test]
+fn terminal_length_takes_whole_words() {
+    let mut shell = TestShell::new(tree());
+    assert_eq!(shell.run("terminal lengthy 5").status, 127);
+    assert!(!shell.run("terminal length 5 6").success());
+    assert!(shell.run("terminal length 0").success());
+}
+
+#[test]
+fn shutdown_hooks_run_on_exit() {
+    let done = Rc::new(Cell::new(false));
+    let mut tree = tree();
+    let flag = done.clone();
+    tree.add_shutdown_hook(move |_router| flag.set(true));
+    let shell = TestShell::new(tree);
+    assert!(!done.get());
+    shell.finish();
+    assert!(done.get());
+}
+
+fn set_key(args: HashMap<String, &str>, _router: &mut Option<Router>, _session: &mut Session) -> CommandResult<Option<String>> {
+    shell_println!("{} is now {}", args["key"], args["value"]);
+    Ok(None)
+}
+
+#[test]
+fn tree_commands_win_over_builtins() {
+    let tree = shell_command_tree!{switch,
+        "Switch",
+        "1.0",
+        Router::default(),
+        [
+            shell_command_node!{
+                cmd: interface,
+                txt_help: "Configure an interface",
+                callback: interface,
+                context: true,
+                args: [name => true],
+                nodes: [
+                    shell_command_node!{
+                        cmd: set,
+                        txt_help: "Set an interface option",
+                        callback: set_key,
+                        args: [key => true, value => true]
+                    }
+                ]
+            },
+            shell_command_node!{
+                cmd: terminal,
+                txt_help: "Configure the console port",
+                nodes: [
+                    shell_command_node!{
+                        cmd: length,
+                        txt_help: "Set the console page length",
+                        callback: set_key,
+                        args: [key => true, value => true]
+                    }
+                ]
+            }
+        ]
+    };
+    let mut shell = TestShell::new(tree);
+    shell.run("interface eth0");
+    assert_eq!(shell.run("set speed 1000").output, "speed is now 1000\n");
+    assert!(shell.session().var("speed").is_none());
+
+
+    shell.run("up");
+    assert!(shell.run("set speed 1000").success());
+    assert_eq!(shell.session().var("speed"), Some("1000".to_owned()));
+    assert_eq!(shell.run("terminal length rows 40").output, "rows is now 40\n");
+}
+
+#[test]
+fn set_keeps_quoted_values() {
+    let mut shell = TestShell::new(tree());
+    shell.run("set pad \"  a  \"");
+    assert_eq!(shell.session().var("pad"), Some("  a  ".to_owned()));
+    shell.run("set quote \"\\\"q\\\"\"");
+    assert_eq!(shell.session().var("quote"), Some("\"q\"".to_owned()));
+    shell.run("set joined=a b");
+    assert_eq!(shell.session().var("joined"), Some("a b".to_owned()));
+    shell.run("set spaced = c");
+    assert_eq!(shell.session().var("spaced"), Some("c".to_owned()));
+}
+
+#[test]
+fn contexts_by_path_replace_the_stack() {
+    let mut shell = TestShell::new(tree());
+    shell.run("interface eth0");
+    shell.run("/interface eth1");
+    shell.assert_context(&["eth1"]);
+    shell.run("../interface eth2");
+    shell.assert_context(&["eth2"]);
+
+    // The callback sees the context it runs in, the stack is back afterwards
+    assert_eq!(shell.run("/interface eth3 mtu 1500").output, "eth3 mtu 1500\n");
+    shell.assert_context(&["eth2"]);
+    assert_eq!(shell.session().depth(), 1);
+}
+
+#[test]
+fn question_mark_after_args_shows_help() {
+    let ran = Rc::new(Cell::new(false));
+    let mut tree = tree();
+    let flag = ran.clone();
+    tree.before_command(move |_path, _args, _router| {
+        flag.set(true);
+        Ok(())
+    });
+    let mut shell = TestShell::new(tree);
+    let out = shell.run("interface eth0 ?");
+    assert!(out.output.contains("Configure an interface"));
+    assert!(!ran.get());
+}
+
+#[test]
+fn bad_set_and_unset_fail() {
+    let mut shell = TestShell::new(tree());
+    let out = shell.run("set 1x foo");
+    assert_eq!(out.errors, "Error: bad variable name '1x'\n");
+    assert_eq!(out.status, 1);
+    assert_eq!(shell.run("unset").status, 1);
+    assert!(shell.run("unset missing").success());
+}
+
+#[test]
+fn test_shells_are_plain_whatever_the_tree() {
+    let mut tree = tree();
+    tree.set_color(true);
+    let mut shell = TestShell::new(tree);
+    assert!(!shell.run("help").output.contains('\x1b'));
+    assert!(!shell.run("bogus").errors.contains('\x1b'));
+}
+
+fn bad_context(_args: HashMap<String, &str>, _router: &mut Option<Router>, _session: &mut Session) -> CommandResult<Option<String>> {
+    Ok(Some("eth0".to_owned()))
+}
+
+#[test]
+fn context_without_object_fails_the_line() {
+    let tree = shell_command_tree!{switch,
+        "Switch",
+        "1.0",
+        Router::default(),
+        [
+            shell_command_node!{
+                cmd: port,
+                txt_help: "Configure a port",
+                callback: bad_context,
+                context: true
+            }
+        ]
+    };
+    let mut shell = TestShell::new(tree);
+    let out = shell.run("port");
+    assert_eq!(out.errors, "Error: 'port' returned 'eth0', contexts are entered with 'object:label'\n");
+    assert_eq!(out.status, 1);
+    shell.assert_context(&[]);
+}